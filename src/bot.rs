@@ -0,0 +1,1177 @@
+use crate::config::{Config, MultiReply};
+use crate::envelope::{Attachment, Envelope};
+use crate::error::AnalysisError;
+use crate::link::{self, CanonicalVideo, Link};
+use crate::messenger::Messenger;
+use crate::pipeline::{Analysis, VideoSource};
+use crate::platform::Platforms;
+use crate::progress::{self, Feedback, Progress};
+use crate::queue::{Admission, JobQueue};
+use crate::rpc::{Quote, ReplyTarget, RpcError};
+use crate::sheets;
+use crate::store::JobStore;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+// How many times a reply is re-sent after losing the signal-cli connection
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+
+// The analysis pipeline as seen by the bot: `pipeline::analyze_video` in
+// production, a canned answer in tests. Takes the language to reply in.
+pub type AnalyzeFn = Arc<
+    dyn Fn(
+            VideoSource,
+            Option<String>,
+            Progress,
+        ) -> Pin<Box<dyn Future<Output = Result<Analysis, AnalysisError>> + Send>>
+        + Send
+        + Sync,
+>;
+
+// One video to analyze and where the answer should go
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub source: VideoSource,
+    pub label: String,
+    // Phone number or UUID of whoever sent the link
+    pub sender: String,
+    // None when the link doesn't tell us which video it is (short links,
+    // attachments); such jobs are never served from the cache
+    pub video: Option<CanonicalVideo>,
+    pub reply_target: ReplyTarget,
+    pub quote: Option<Quote>,
+    // Set when this is one of several videos answered in a single reply
+    pub batch: Option<Batch>,
+    // Language the summary should be written in, when configured for the recipient
+    pub reply_language: Option<String>,
+}
+
+// Jobs from the same message that share one combined reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch {
+    // Same for every job of the message
+    pub key: String,
+    // Position of this video in the message, from 0
+    pub index: usize,
+    pub size: usize,
+}
+
+// Decides which envelopes deserve an analysis and who gets the reply
+pub struct Router {
+    config: Config,
+    platforms: Arc<Platforms>,
+}
+
+impl Router {
+    pub fn new(config: Config) -> Self {
+        let platforms = Platforms::select(&config.platforms)
+            .expect("platform names are checked by Config::from_env");
+        Self {
+            config,
+            platforms: Arc::new(platforms),
+        }
+    }
+
+    pub fn platforms(&self) -> Arc<Platforms> {
+        self.platforms.clone()
+    }
+
+    pub fn route(&self, envelope: &Envelope) -> Vec<Job> {
+        // Get source identifier - prefer phone number, fallback to UUID
+        let source = envelope
+            .source_number
+            .clone()
+            .or_else(|| envelope.source_uuid.clone());
+
+        let Some(source) = source else {
+            println!("[DEBUG] No sourceNumber or sourceUuid in envelope");
+            return Vec::new();
+        };
+
+        println!("[DEBUG] Message from source: {}", source);
+        println!(
+            "[DEBUG] Has dataMessage: {}",
+            envelope.data_message.is_some()
+        );
+        println!(
+            "[DEBUG] Has syncMessage: {}",
+            envelope.sync_message.is_some()
+        );
+
+        let mut text_content = None;
+        let mut attachments: &[Attachment] = &[];
+        let mut recipient = ReplyTarget::Direct(source.clone());
+
+        // Check standard message (from others), either direct or in a group
+        if let Some(ref data) = envelope.data_message {
+            println!("[DEBUG] dataMessage.message: {:?}", data.message);
+            if let Some(ref group) = data.group_info {
+                if !self.config.group_enabled(&group.group_id) {
+                    println!(
+                        "[DEBUG] Group {} has not opted in, skipping",
+                        group.group_id
+                    );
+                    return Vec::new();
+                }
+                recipient = ReplyTarget::Group(group.group_id.clone());
+            }
+            text_content = data.message.clone();
+            attachments = &data.attachments;
+        }
+        // Check "Note to Self" (Sync)
+        else if let Some(ref sync) = envelope.sync_message {
+            if let Some(ref sent) = sync.sent_message {
+                println!(
+                    "[DEBUG] syncMessage.sentMessage.destination: {:?}",
+                    sent.destination
+                );
+                println!(
+                    "[DEBUG] syncMessage.sentMessage.message: {:?}",
+                    sent.message
+                );
+                if sent.destination == Some(source.clone()) {
+                    text_content = sent.message.clone();
+                    attachments = &sent.attachments;
+                }
+                // Our own links posted to an opted-in group from another device
+                else if let Some(ref group) = sent.group_info
+                    && self.config.group_enabled(&group.group_id)
+                {
+                    recipient = ReplyTarget::Group(group.group_id.clone());
+                    text_content = sent.message.clone();
+                    attachments = &sent.attachments;
+                }
+            } else {
+                println!("[DEBUG] syncMessage has no sentMessage");
+            }
+        }
+
+        if text_content.is_none() && attachments.is_empty() {
+            println!("[DEBUG] No text content or attachments extracted, skipping");
+            return Vec::new();
+        }
+        let text = text_content.unwrap_or_default();
+
+        println!(
+            "[DEBUG] Extracted text: {}",
+            &text[..text.floor_char_boundary(100)]
+        );
+
+        let source_id = source.clone();
+        // Thread the summary under the message that contained the link
+        let quote = envelope.timestamp.map(|timestamp| Quote {
+            timestamp,
+            author: source.clone(),
+            message: text.clone(),
+        });
+        let reply_language = self.config.reply_language_for(&recipient);
+        let job = |source, label: &str, video| Job {
+            source,
+            label: label.to_string(),
+            sender: source_id.clone(),
+            video,
+            reply_target: recipient.clone(),
+            quote: quote.clone(),
+            batch: None,
+            reply_language: reply_language.clone(),
+        };
+
+        // Forwarded video files skip the download step entirely
+        let videos: Vec<&Attachment> = attachments
+            .iter()
+            .filter(|a| {
+                a.content_type
+                    .as_deref()
+                    .is_some_and(|t| t.starts_with("video/"))
+            })
+            .collect();
+        let mut jobs = Vec::new();
+        if !videos.is_empty() {
+            for attachment in videos {
+                let Some(ref id) = attachment.id else {
+                    println!("[DEBUG] Video attachment without id, skipping");
+                    continue;
+                };
+                println!(
+                    "🎞️ Video attachment {:?} detected from {}",
+                    attachment.filename.as_deref().unwrap_or(id),
+                    recipient
+                );
+                let path = self.config.attachments_dir.join(id);
+                jobs.push(job(VideoSource::File(path), "video attachment", None));
+            }
+        } else {
+            for link in link::find(&text, &self.platforms) {
+                let Some(platform) = self.platforms.get(link.platform()) else {
+                    continue;
+                };
+                println!(
+                    "{} {} detected from {}",
+                    platform.emoji(),
+                    platform.display_name(),
+                    recipient
+                );
+                let (url, video) = match link {
+                    Link::Video(video) => (video.url.clone(), Some(video)),
+                    Link::Short { url, .. } => (url, None),
+                };
+                jobs.push(job(VideoSource::Url(url), platform.display_name(), video));
+            }
+            if jobs.is_empty() {
+                println!("[DEBUG] No matching URL patterns found");
+            }
+        }
+
+        // Several videos in one message get one numbered answer
+        if self.config.multi_reply == MultiReply::Combined
+            && jobs.len() > 1
+            && let Some(timestamp) = envelope.timestamp
+        {
+            let size = jobs.len();
+            for (index, job) in jobs.iter_mut().enumerate() {
+                job.batch = Some(Batch {
+                    key: format!("{}:{}", source, timestamp),
+                    index,
+                    size,
+                });
+            }
+        }
+        jobs
+    }
+}
+
+// What admitting a job needs to know besides the job itself
+struct Admit {
+    cache_ttl: Option<Duration>,
+    repeat_note: bool,
+    // Where contact sheets wait to go out with their reply
+    previews: PathBuf,
+}
+
+// Main Loop: route every incoming envelope and hand its jobs to a fixed pool
+// of workers. Jobs and replies are persisted in `store`, so whatever was left
+// over from the previous run is resumed first. Returns once the messenger runs
+// dry and all jobs have replied.
+pub async fn run<M: Messenger>(
+    messenger: Arc<M>,
+    config: Config,
+    analyze: AnalyzeFn,
+    store: Arc<JobStore>,
+) {
+    let queue = Arc::new(JobQueue::new(config.workers, config.max_queue));
+    let settings = Arc::new(Admit {
+        cache_ttl: config.cache_ttl,
+        repeat_note: config.repeat_note,
+        previews: config.previews_dir(),
+    });
+    let router = Router::new(config);
+    let platforms = router.platforms();
+
+    // Re-sends and admissions run as their own tasks: resolving a short link
+    // takes a network round trip, and a slow send shouldn't hold up the next
+    // message. All of them are joined before returning.
+    let mut tasks = JoinSet::new();
+    resume(&messenger, &queue, &store, &settings.previews, &mut tasks);
+
+    let mut workers = JoinSet::new();
+    for _ in 0..queue.workers() {
+        let messenger = messenger.clone();
+        let analyze = analyze.clone();
+        let queue = queue.clone();
+        let store = store.clone();
+        let settings = settings.clone();
+        workers.spawn(async move {
+            while let Some((id, job)) = queue.pop().await {
+                let previews = &settings.previews;
+                run_job(
+                    messenger.clone(),
+                    analyze.clone(),
+                    &store,
+                    previews,
+                    id,
+                    job,
+                )
+                .await;
+                queue.done();
+            }
+        });
+    }
+
+    println!("[DEBUG] Entering main event loop, waiting for messages...");
+    while let Some(envelope) = messenger.receive().await {
+        for job in router.route(&envelope) {
+            tasks.spawn(admit(
+                messenger.clone(),
+                queue.clone(),
+                store.clone(),
+                platforms.clone(),
+                settings.clone(),
+                job,
+            ));
+        }
+        while tasks.try_join_next().is_some() {}
+    }
+    while tasks.join_next().await.is_some() {}
+
+    queue.close();
+    while workers.join_next().await.is_some() {}
+}
+
+// Get a routed job into the queue, or answer it straight from the cache
+async fn admit<M: Messenger>(
+    messenger: Arc<M>,
+    queue: Arc<JobQueue<(i64, Job)>>,
+    store: Arc<JobStore>,
+    platforms: Arc<Platforms>,
+    settings: Arc<Admit>,
+    mut job: Job,
+) {
+    // Short links only reveal the video after following them; if that fails
+    // yt-dlp gets the short link as is
+    if job.video.is_none()
+        && let VideoSource::Url(ref url) = job.source
+    {
+        match link::resolve(url, &platforms).await {
+            Ok(video) => {
+                job.source = VideoSource::Url(video.url.clone());
+                job.video = Some(video);
+            }
+            Err(e) => eprintln!("Warning: Failed to resolve {}: {:#}", url, e),
+        }
+    }
+
+    let id = match store.insert(&job) {
+        Ok(id) => id,
+        Err(e) => {
+            eprintln!("❌ Failed to store job for {}: {:#}", job.reply_target, e);
+            let error = AnalysisError::from(e);
+            let reply = error.user_message();
+            // The rest of its batch still needs this part to complete
+            if job.batch.is_some() {
+                match store.insert_failed(&job, &reply, &error.to_string()) {
+                    Ok(id) => {
+                        let previews = &settings.previews;
+                        send_result(&*messenger, &store, previews, id, &job, &reply).await;
+                        return;
+                    }
+                    Err(e) => eprintln!("❌ Failed to store failed job: {:#}", e),
+                }
+            }
+            deliver_reply(
+                &*messenger,
+                &job.reply_target,
+                job.quote.as_ref(),
+                &reply,
+                &[],
+            )
+            .await;
+            return;
+        }
+    };
+
+    let previews = &settings.previews;
+    if let Some(ttl) = settings.cache_ttl
+        && let Some(summary) = cached_summary(&store, &job, ttl)
+    {
+        log_store_error(store.mark_cached(id, &summary));
+        let reply = if settings.repeat_note {
+            with_repeat_note(&store, &job, summary)
+        } else {
+            summary
+        };
+        send_result(&*messenger, &store, previews, id, &job, &reply).await;
+        return;
+    }
+
+    // Acknowledge on receipt rather than when a worker gets to it, once per
+    // message. Awaited, so ⏳ always lands before the outcome replaces it.
+    let acknowledged = match job.quote {
+        Some(ref message) if job.batch.as_ref().is_none_or(|b| b.index == 0) => {
+            progress::acknowledge(&*messenger, &job.reply_target, message).await;
+            true
+        }
+        _ => false,
+    };
+
+    let admitted = job.clone();
+    let notice = match queue.push((id, job)) {
+        Admission::Started => return,
+        Admission::Queued(position) => {
+            println!(
+                "[DEBUG] Job for {} queued at #{}",
+                admitted.reply_target, position
+            );
+            // One notice per message is plenty
+            if admitted.batch.as_ref().is_some_and(|b| b.index > 0) {
+                return;
+            }
+            format!(
+                "📋 Lots of brainrot right now, yours is #{} in the queue.",
+                position
+            )
+        }
+        Admission::Rejected => {
+            println!(
+                "[DEBUG] Queue full, rejecting job for {}",
+                admitted.reply_target
+            );
+            let notice = "🚦 I'm swamped with videos right now, please send that one again in a few minutes.";
+            // The rest of its batch still needs this part to complete
+            if admitted.batch.is_some() {
+                log_store_error(store.mark_finished(id, notice, Some("queue full")));
+                send_result(&*messenger, &store, previews, id, &admitted, notice).await;
+                return;
+            }
+            log_store_error(store.remove(id));
+            if acknowledged && let Some(ref message) = admitted.quote {
+                progress::decline(&*messenger, &admitted.reply_target, message).await;
+            }
+            notice.to_string()
+        }
+    };
+    deliver_reply(
+        &*messenger,
+        &admitted.reply_target,
+        admitted.quote.as_ref(),
+        &notice,
+        &[],
+    )
+    .await;
+}
+
+fn cached_summary(store: &JobStore, job: &Job, ttl: Duration) -> Option<String> {
+    let video = job.video.as_ref()?;
+    // A summary in another language is no use to this recipient
+    match store.cached(video, ttl, job.reply_language.as_deref()) {
+        Ok(Some(summary)) => {
+            println!(
+                "♻️ Reusing summary of {} {} for {}",
+                video.platform, video.id, job.reply_target
+            );
+            Some(summary)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            eprintln!("❌ Failed to look up cached summary: {:#}", e);
+            None
+        }
+    }
+}
+
+// "Also sent by 2 others today", when the video is making the rounds
+fn with_repeat_note(store: &JobStore, job: &Job, summary: String) -> String {
+    let Some(ref video) = job.video else {
+        return summary;
+    };
+    match store.other_senders_today(video, &job.sender) {
+        Ok(0) => summary,
+        Ok(1) => format!("{}\n\n👀 Also sent by 1 other today.", summary),
+        Ok(n) => format!("{}\n\n👀 Also sent by {} others today.", summary, n),
+        Err(e) => {
+            eprintln!("❌ Failed to count repeat senders: {:#}", e);
+            summary
+        }
+    }
+}
+
+// Pick up where the previous run left off: re-send finished but undelivered
+// replies and put interrupted jobs back in line.
+fn resume<M: Messenger>(
+    messenger: &Arc<M>,
+    queue: &JobQueue<(i64, Job)>,
+    store: &Arc<JobStore>,
+    previews: &Path,
+    tasks: &mut JoinSet<()>,
+) {
+    log_store_error(store.unclaim_batches());
+    match store.undelivered() {
+        Ok(pending) => {
+            let mut batches = Vec::new();
+            for reply in pending {
+                // Combined replies go out once per batch, and only when complete
+                if let Some(key) = reply.batch {
+                    if !batches.contains(&key) {
+                        batches.push(key.clone());
+                        let messenger = messenger.clone();
+                        let store = store.clone();
+                        let previews = previews.to_path_buf();
+                        tasks.spawn(async move {
+                            let target = reply.reply_target;
+                            let quote = reply.quote;
+                            finish_batch(
+                                &*messenger,
+                                &store,
+                                &previews,
+                                &key,
+                                &target,
+                                quote.as_ref(),
+                            )
+                            .await;
+                        });
+                    }
+                    continue;
+                }
+                println!("[DEBUG] Re-sending undelivered reply for job {}", reply.id);
+                let messenger = messenger.clone();
+                let store = store.clone();
+                let previews = previews.to_path_buf();
+                tasks.spawn(async move {
+                    deliver_job_reply(
+                        &*messenger,
+                        &store,
+                        &previews,
+                        reply.id,
+                        &reply.reply_target,
+                        reply.quote.as_ref(),
+                        &reply.reply,
+                    )
+                    .await;
+                });
+            }
+        }
+        Err(e) => eprintln!("❌ Failed to load undelivered replies: {:#}", e),
+    }
+
+    match store.unfinished() {
+        Ok(jobs) => {
+            for (id, job) in jobs {
+                println!(
+                    "[DEBUG] Resuming {} job {} for {}",
+                    job.label, id, job.reply_target
+                );
+                queue.requeue((id, job));
+            }
+        }
+        Err(e) => eprintln!("❌ Failed to load unfinished jobs: {:#}", e),
+    }
+}
+
+async fn run_job<M: Messenger>(
+    messenger: Arc<M>,
+    analyze: AnalyzeFn,
+    store: &JobStore,
+    previews: &Path,
+    id: i64,
+    job: Job,
+) {
+    log_store_error(store.mark_running(id));
+    // A batch reacts once, for all its parts, when the combined reply goes out
+    let react_to = job.quote.clone().filter(|_| job.batch.is_none());
+    let (feedback, progress) =
+        Feedback::start(messenger.clone(), job.reply_target.clone(), react_to);
+
+    let reply = match analyze(job.source.clone(), job.reply_language.clone(), progress).await {
+        Ok(analysis) => {
+            feedback.finish(true).await;
+            log_store_error(store.mark_finished(id, &analysis.summary, None));
+            if let Some(ref language) = analysis.language {
+                log_store_error(store.set_language(id, language));
+            }
+            if let Err(e) = sheets::keep_previews(previews, id, &analysis.previews) {
+                eprintln!("Warning: {:#}", e);
+            }
+            analysis.summary
+        }
+        Err(e) => {
+            feedback.finish(false).await;
+            // Raw details stay in the logs; the sender gets the friendly version
+            eprintln!(
+                "❌ Error processing {} from {}: {}",
+                job.label, job.reply_target, e
+            );
+            let reply = e.user_message();
+            log_store_error(store.mark_finished(id, &reply, Some(&e.to_string())));
+            reply
+        }
+    };
+
+    send_result(&*messenger, store, previews, id, &job, &reply).await;
+}
+
+// Send a finished job's reply; a batched job instead completes its share of
+// the combined reply, which goes out with the batch's last video
+async fn send_result<M: Messenger>(
+    messenger: &M,
+    store: &JobStore,
+    previews: &Path,
+    id: i64,
+    job: &Job,
+    reply: &str,
+) {
+    match job.batch {
+        Some(ref batch) => {
+            finish_batch(
+                messenger,
+                store,
+                previews,
+                &batch.key,
+                &job.reply_target,
+                job.quote.as_ref(),
+            )
+            .await
+        }
+        None => {
+            deliver_job_reply(
+                messenger,
+                store,
+                previews,
+                id,
+                &job.reply_target,
+                job.quote.as_ref(),
+                reply,
+            )
+            .await
+        }
+    }
+}
+
+// A single job's reply, with its contact sheets if it has any
+async fn deliver_job_reply<M: Messenger>(
+    messenger: &M,
+    store: &JobStore,
+    previews: &Path,
+    id: i64,
+    recipient: &ReplyTarget,
+    quote: Option<&Quote>,
+    reply: &str,
+) {
+    let attachments = sheets::previews(previews, id);
+    if deliver_reply(messenger, recipient, quote, reply, &attachments).await {
+        log_store_error(store.mark_delivered(id));
+        sheets::discard_previews(previews, id);
+    }
+}
+
+async fn finish_batch<M: Messenger>(
+    messenger: &M,
+    store: &JobStore,
+    previews: &Path,
+    key: &str,
+    recipient: &ReplyTarget,
+    quote: Option<&Quote>,
+) {
+    let parts = match store.complete_batch(key) {
+        Ok(Some(parts)) => parts,
+        // Others are still being analyzed
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("❌ Failed to collect batch {}: {:#}", key, e);
+            return;
+        }
+    };
+    let reply = parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| format!("{}. {}\n{}", i + 1, part.source, part.reply))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let attachments: Vec<PathBuf> = parts
+        .iter()
+        .flat_map(|part| sheets::previews(previews, part.id))
+        .collect();
+    if deliver_reply(messenger, recipient, quote, &reply, &attachments).await {
+        for part in &parts {
+            log_store_error(store.mark_delivered(part.id));
+            sheets::discard_previews(previews, part.id);
+        }
+        if let Some(message) = quote {
+            let success = !parts.iter().any(|part| part.failed);
+            progress::conclude(messenger, recipient, message, success).await;
+        }
+    } else {
+        log_store_error(store.release_batch(key));
+    }
+}
+
+fn log_store_error(result: anyhow::Result<()>) {
+    if let Err(e) = result {
+        eprintln!("❌ Job store error: {:#}", e);
+    }
+}
+
+// Replies survive signal-cli restarts: a closed transport means the request was
+// never answered, so wait for the supervisor to bring it back and try again.
+async fn deliver_reply<M: Messenger>(
+    messenger: &M,
+    recipient: &ReplyTarget,
+    quote: Option<&Quote>,
+    message: &str,
+    attachments: &[PathBuf],
+) -> bool {
+    for attempt in 1..=MAX_DELIVERY_ATTEMPTS {
+        match messenger.send(recipient, message, quote, attachments).await {
+            Ok(timestamp) => {
+                println!("✅ Sent reply to {} (timestamp {})", recipient, timestamp);
+                return true;
+            }
+            Err(RpcError::Closed) if attempt < MAX_DELIVERY_ATTEMPTS => {
+                println!(
+                    "[DEBUG] signal-cli went away while replying to {}, retrying...",
+                    recipient
+                );
+            }
+            Err(e) => {
+                eprintln!("❌ Failed to deliver reply to {}: {}", recipient, e);
+                return false;
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messenger::fake::FakeMessenger;
+    use std::path::PathBuf;
+
+    const ME: &str = "+4500000000";
+    const FRIEND: &str = "+4511111111";
+    const GROUP: &str = "Z3JvdXAtaWQ=";
+
+    fn config() -> Config {
+        Config {
+            enabled_groups: vec![GROUP.to_string()],
+            attachments_dir: PathBuf::from("/attachments"),
+            workers: 1,
+            max_queue: 5,
+            cache_ttl: Some(Duration::from_secs(3600)),
+            repeat_note: true,
+            ..Default::default()
+        }
+    }
+
+    // Echoes the source back so tests can see what was analyzed
+    fn echo_analyzer() -> AnalyzeFn {
+        Arc::new(|source, _reply_language, _progress| {
+            Box::pin(async move {
+                let summary = match source {
+                    VideoSource::Url(url) => format!("Summary of {}", url),
+                    VideoSource::File(path) => format!("Summary of {}", path.display()),
+                };
+                Ok(Analysis {
+                    summary,
+                    previews: Vec::new(),
+                    language: None,
+                })
+            })
+        })
+    }
+
+    fn direct(from: &str, text: &str) -> String {
+        serde_json::json!({
+            "sourceNumber": from,
+            "timestamp": 1700000000001u64,
+            "dataMessage": { "message": text },
+        })
+        .to_string()
+    }
+
+    fn in_group(from: &str, group: &str, text: &str) -> String {
+        serde_json::json!({
+            "sourceNumber": from,
+            "timestamp": 1700000000002u64,
+            "dataMessage": { "message": text, "groupInfo": { "groupId": group, "type": "DELIVER" } },
+        })
+        .to_string()
+    }
+
+    async fn run_script(envelopes: &[String], analyze: AnalyzeFn) -> Arc<FakeMessenger> {
+        run_with(envelopes, analyze, config()).await
+    }
+
+    async fn run_with(
+        envelopes: &[String],
+        analyze: AnalyzeFn,
+        config: Config,
+    ) -> Arc<FakeMessenger> {
+        let envelopes: Vec<&str> = envelopes.iter().map(String::as_str).collect();
+        let messenger = Arc::new(FakeMessenger::with_envelopes(&envelopes));
+        let store = Arc::new(JobStore::open_in_memory().unwrap());
+        run(messenger.clone(), config, analyze, store).await;
+        messenger
+    }
+
+    fn tiktok_job(sender: &str, n: u32) -> Job {
+        Job {
+            source: VideoSource::Url(format!("https://www.tiktok.com/@user/video/{}", n)),
+            label: "TikTok".to_string(),
+            sender: sender.to_string(),
+            video: Some(CanonicalVideo {
+                platform: "tiktok".to_string(),
+                id: n.to_string(),
+                url: format!("https://www.tiktok.com/@user/video/{}", n),
+            }),
+            reply_target: ReplyTarget::Direct(sender.to_string()),
+            quote: None,
+            batch: None,
+            reply_language: None,
+        }
+    }
+
+    fn route(envelope: &str) -> Vec<Job> {
+        Router::new(config()).route(&serde_json::from_str(envelope).unwrap())
+    }
+
+    #[test]
+    fn detects_tiktok_and_instagram_links() {
+        let jobs = route(&direct(FRIEND, "lol https://vm.tiktok.com/ZMabc123/ look"));
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].label, "TikTok");
+        assert_eq!(
+            jobs[0].source,
+            VideoSource::Url("https://vm.tiktok.com/ZMabc123/".to_string())
+        );
+
+        // Short links don't say which video they are
+        assert_eq!(jobs[0].video, None);
+
+        let jobs = route(&direct(FRIEND, "https://www.instagram.com/reel/C1abc/"));
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].label, "Instagram");
+        assert_eq!(
+            jobs[0].video,
+            Some(CanonicalVideo {
+                platform: "instagram".to_string(),
+                id: "C1abc".to_string(),
+                url: "https://www.instagram.com/reel/C1abc/".to_string(),
+            })
+        );
+
+        let jobs = route(&direct(FRIEND, "https://youtube.com/shorts/dQw4w9WgXcQ"));
+        assert_eq!(jobs[0].label, "YouTube Short");
+
+        assert!(route(&direct(FRIEND, "no links here")).is_empty());
+        assert!(route(&direct(FRIEND, "https://example.com/video")).is_empty());
+    }
+
+    #[test]
+    fn long_non_ascii_messages_do_not_panic() {
+        // 99 ASCII bytes, then an emoji straddling byte 100
+        let text = format!(
+            "{}😭 https://www.tiktok.com/@user/video/123",
+            "a".repeat(99)
+        );
+        assert_eq!(route(&direct(FRIEND, &text)).len(), 1);
+    }
+
+    #[test]
+    fn routes_video_attachments_to_the_attachment_store() {
+        let envelope = serde_json::json!({
+            "sourceNumber": FRIEND,
+            "timestamp": 1u64,
+            "dataMessage": {
+                "message": null,
+                "attachments": [
+                    { "contentType": "image/jpeg", "id": "pic.jpg" },
+                    { "contentType": "video/mp4", "id": "abc.mp4", "filename": "reel.mp4" },
+                ],
+            },
+        })
+        .to_string();
+
+        let jobs = route(&envelope);
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(
+            jobs[0].source,
+            VideoSource::File(PathBuf::from("/attachments/abc.mp4"))
+        );
+    }
+
+    #[test]
+    fn ignores_groups_that_have_not_opted_in() {
+        let text = "https://www.tiktok.com/@user/video/123";
+        assert!(route(&in_group(FRIEND, "b3RoZXI=", text)).is_empty());
+        assert_eq!(
+            route(&in_group(FRIEND, GROUP, text))[0].reply_target,
+            ReplyTarget::Group(GROUP.to_string())
+        );
+    }
+
+    #[test]
+    fn note_to_self_replies_to_self() {
+        let envelope = serde_json::json!({
+            "sourceNumber": ME,
+            "timestamp": 3u64,
+            "syncMessage": { "sentMessage": {
+                "destination": ME,
+                "message": "https://www.tiktok.com/@user/video/123",
+            } },
+        })
+        .to_string();
+
+        let jobs = route(&envelope);
+        assert_eq!(jobs[0].reply_target, ReplyTarget::Direct(ME.to_string()));
+
+        // Our own messages to other people are none of the bot's business
+        let envelope = envelope.replace(
+            &format!("\"destination\":\"{}\"", ME),
+            &format!("\"destination\":\"{}\"", FRIEND),
+        );
+        assert!(route(&envelope).is_empty());
+    }
+
+    #[tokio::test]
+    async fn replies_to_sender_as_quote() {
+        let text = "check this https://www.tiktok.com/@user/video/123";
+        let messenger = run_script(&[direct(FRIEND, text)], echo_analyzer()).await;
+
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        let (target, message, quote) = &messages[0];
+        assert_eq!(*target, ReplyTarget::Direct(FRIEND.to_string()));
+        assert_eq!(message, "Summary of https://www.tiktok.com/@user/video/123");
+        let quote = quote.as_ref().unwrap();
+        assert_eq!(quote.timestamp, 1700000000001);
+        assert_eq!(quote.author, FRIEND);
+        assert_eq!(quote.message, text);
+
+        assert_eq!(messenger.reactions().last().map(String::as_str), Some("✅"));
+    }
+
+    #[tokio::test]
+    async fn attaches_contact_sheets_and_cleans_them_up() {
+        let work_root =
+            std::env::temp_dir().join(format!("brainrot-bot-previews-{}", std::process::id()));
+        let config = Config {
+            work_root: work_root.clone(),
+            ..config()
+        };
+        let previews = config.previews_dir();
+        std::fs::create_dir_all(&previews).unwrap();
+        let parked = previews.join("pending-job-1.jpg");
+        std::fs::write(&parked, "sheet").unwrap();
+
+        let with_sheet: AnalyzeFn = Arc::new(move |_, _, _| {
+            let parked = parked.clone();
+            Box::pin(async move {
+                Ok(Analysis {
+                    summary: "Summary".to_string(),
+                    previews: vec![parked],
+                    language: None,
+                })
+            })
+        });
+        let text = "https://www.tiktok.com/@user/video/123";
+        let messenger = run_with(&[direct(FRIEND, text)], with_sheet, config).await;
+
+        let attachments = messenger.attachments();
+        let leftovers = std::fs::read_dir(&previews).unwrap().count();
+        std::fs::remove_dir_all(&work_root).unwrap();
+        assert_eq!(attachments, [vec![previews.join("job-1-1.jpg")]]);
+        assert_eq!(leftovers, 0);
+    }
+
+    #[tokio::test]
+    async fn replies_into_the_group() {
+        let text = "https://www.instagram.com/reel/C1abc/";
+        let messenger = run_script(&[in_group(FRIEND, GROUP, text)], echo_analyzer()).await;
+
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, ReplyTarget::Group(GROUP.to_string()));
+    }
+
+    #[tokio::test]
+    async fn failed_analysis_tells_the_sender_without_raw_details() {
+        let failing: AnalyzeFn = Arc::new(|_, _, _| {
+            Box::pin(async {
+                Err(AnalysisError::from_ytdlp(
+                    "ERROR: [TikTok] 123: This video is private",
+                ))
+            })
+        });
+        let text = "https://www.tiktok.com/@user/video/123";
+        let messenger = run_script(&[direct(FRIEND, text)], failing).await;
+
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].1.contains("private"));
+        assert!(!messages[0].1.contains("ERROR"));
+        assert_eq!(messenger.reactions().last().map(String::as_str), Some("❌"));
+    }
+
+    #[tokio::test]
+    async fn acknowledges_links_while_they_wait_in_the_queue() {
+        let slow: AnalyzeFn = Arc::new(|_, _, _| {
+            Box::pin(async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                Ok(Analysis {
+                    summary: "Summary".to_string(),
+                    previews: Vec::new(),
+                    language: None,
+                })
+            })
+        });
+        let envelopes = [
+            direct(FRIEND, "https://www.tiktok.com/@user/video/1"),
+            direct(FRIEND, "https://www.tiktok.com/@user/video/2"),
+        ];
+        let messenger = run_script(&envelopes, slow).await;
+
+        // The second link is acknowledged before the first one is done
+        assert_eq!(messenger.reactions(), ["⏳", "⏳", "✅", "✅"]);
+    }
+
+    #[tokio::test]
+    async fn full_queue_politely_rejects() {
+        let config = Config {
+            max_queue: 1,
+            ..config()
+        };
+        let envelopes = [
+            direct(FRIEND, "https://www.tiktok.com/@user/video/1"),
+            direct(FRIEND, "https://www.tiktok.com/@user/video/2"),
+            direct(FRIEND, "https://www.tiktok.com/@user/video/3"),
+        ];
+        let messenger = run_with(&envelopes, echo_analyzer(), config).await;
+
+        let messages: Vec<String> = messenger.messages().into_iter().map(|m| m.1).collect();
+        assert!(messages.iter().any(|m| m.contains("#1 in the queue")));
+        assert!(messages.iter().any(|m| m.contains("swamped")));
+        assert!(messages.contains(&"Summary of https://www.tiktok.com/@user/video/1".to_string()));
+        assert!(messages.contains(&"Summary of https://www.tiktok.com/@user/video/2".to_string()));
+        assert!(!messages.contains(&"Summary of https://www.tiktok.com/@user/video/3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn resumes_jobs_and_replies_from_the_store() {
+        let store = Arc::new(JobStore::open_in_memory().unwrap());
+        // Interrupted mid-analysis
+        let running = store.insert(&tiktok_job(FRIEND, 1)).unwrap();
+        store.mark_running(running).unwrap();
+        // Finished, but the reply never went out
+        let finished = store.insert(&tiktok_job(FRIEND, 2)).unwrap();
+        store.mark_finished(finished, "Old summary", None).unwrap();
+
+        let messenger = Arc::new(FakeMessenger::with_envelopes(&[]));
+        run(messenger.clone(), config(), echo_analyzer(), store.clone()).await;
+
+        let messages: Vec<String> = messenger.messages().into_iter().map(|m| m.1).collect();
+        assert!(messages.contains(&"Old summary".to_string()));
+        assert!(messages.contains(&"Summary of https://www.tiktok.com/@user/video/1".to_string()));
+        assert_eq!(store.status(running).as_deref(), Some("delivered"));
+        assert_eq!(store.status(finished).as_deref(), Some("delivered"));
+    }
+
+    #[tokio::test]
+    async fn repeat_links_are_answered_from_the_cache() {
+        let store = Arc::new(JobStore::open_in_memory().unwrap());
+        for sender in [ME, "+4522222222"] {
+            let id = store.insert(&tiktok_job(sender, 7)).unwrap();
+            store.mark_finished(id, "Cached summary", None).unwrap();
+            store.mark_delivered(id).unwrap();
+        }
+
+        let never: AnalyzeFn = Arc::new(|_, _, _| Box::pin(async { panic!("analyzed twice") }));
+        let text = "https://www.tiktok.com/@someone/video/7?is_from_webapp=1";
+        let messenger = Arc::new(FakeMessenger::with_envelopes(&[&direct(FRIEND, text)]));
+        run(messenger.clone(), config(), never, store).await;
+
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].1,
+            "Cached summary\n\n👀 Also sent by 2 others today."
+        );
+    }
+
+    #[tokio::test]
+    async fn several_links_get_one_numbered_reply() {
+        let text = "https://www.tiktok.com/@a/video/1 vs https://www.instagram.com/reel/C1abc/ \
+                    (https://www.tiktok.com/@a/video/1?lang=en)";
+        let jobs = route(&direct(FRIEND, text));
+        assert_eq!(jobs.len(), 2);
+
+        let messenger = run_script(&[direct(FRIEND, text)], echo_analyzer()).await;
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].1,
+            "1. https://www.tiktok.com/@a/video/1\n\
+             Summary of https://www.tiktok.com/@a/video/1\n\n\
+             2. https://www.instagram.com/reel/C1abc/\n\
+             Summary of https://www.instagram.com/reel/C1abc/"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_part_that_cannot_be_stored_does_not_hold_up_its_batch() {
+        let store = Arc::new(JobStore::open_in_memory().unwrap());
+        store.fail_inserts_where("NEW.batch_index = 1 AND NEW.status = 'queued'");
+        let text = "https://www.tiktok.com/@a/video/1 https://www.tiktok.com/@a/video/2";
+        let messenger = Arc::new(FakeMessenger::with_envelopes(&[&direct(FRIEND, text)]));
+        run(messenger.clone(), config(), echo_analyzer(), store).await;
+
+        let messages = messenger.messages();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(
+            messages[0].1,
+            "1. https://www.tiktok.com/@a/video/1\n\
+             Summary of https://www.tiktok.com/@a/video/1\n\n\
+             2. https://www.tiktok.com/@a/video/2\n\
+             💥 Something went wrong while analyzing that video."
+        );
+    }
+
+    #[tokio::test]
+    async fn a_batch_reacts_once_with_its_overall_outcome() {
+        // The failing part finishes first, the good one last
+        let second_fails: AnalyzeFn = Arc::new(|source, _, _| {
+            Box::pin(async move {
+                match source {
+                    VideoSource::Url(url) if url.ends_with("/2") => Err(AnalysisError::from_ytdlp(
+                        "ERROR: [TikTok] 2: This video is private",
+                    )),
+                    _ => {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok(Analysis {
+                            summary: "Summary".to_string(),
+                            previews: Vec::new(),
+                            language: None,
+                        })
+                    }
+                }
+            })
+        });
+        let config = Config {
+            workers: 2,
+            ..config()
+        };
+        let text = "https://www.tiktok.com/@a/video/1 https://www.tiktok.com/@a/video/2";
+        let messenger = run_with(&[direct(FRIEND, text)], second_fails, config).await;
+
+        assert_eq!(messenger.messages().len(), 1);
+        assert_eq!(messenger.reactions(), ["⏳", "❌"]);
+    }
+
+    #[tokio::test]
+    async fn several_links_can_be_answered_separately() {
+        let config = Config {
+            multi_reply: MultiReply::Separate,
+            ..config()
+        };
+        let text = "https://www.tiktok.com/@a/video/1 https://www.tiktok.com/@a/video/2";
+        let messenger = run_with(&[direct(FRIEND, text)], echo_analyzer(), config).await;
+
+        let messages: Vec<String> = messenger.messages().into_iter().map(|m| m.1).collect();
+        assert!(messages.contains(&"Summary of https://www.tiktok.com/@a/video/1".to_string()));
+        assert!(messages.contains(&"Summary of https://www.tiktok.com/@a/video/2".to_string()));
+    }
+
+    #[test]
+    fn only_enabled_platforms_are_picked_up() {
+        let config = Config {
+            platforms: vec!["instagram".to_string()],
+            ..config()
+        };
+        let text = "https://www.tiktok.com/@a/video/1 https://www.instagram.com/reel/C1abc/";
+        let jobs = Router::new(config).route(&serde_json::from_str(&direct(FRIEND, text)).unwrap());
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].label, "Instagram");
+    }
+}
@@ -0,0 +1,384 @@
+use crate::frames::{Sampling, Strategy};
+use crate::language;
+use crate::platform::Platforms;
+use crate::retry::RetryPolicy;
+use crate::rpc::ReplyTarget;
+use crate::sheets::Sheets;
+use crate::workspace::Retention;
+use anyhow::{Context, Result, bail};
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+// Runtime configuration, read from environment variables so it can live in
+// the systemd unit (or a .env file loaded by it).
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub transport: Transport,
+    // Group ids (base64, as signal-cli prints them) the bot may reply in
+    pub enabled_groups: Vec<String>,
+    // Where signal-cli stores received attachments
+    pub attachments_dir: PathBuf,
+    // Every job gets its own directory under here
+    pub work_root: PathBuf,
+    pub retention: Retention,
+    // Jobs analyzed at the same time, and how many more may wait in line
+    pub workers: usize,
+    pub max_queue: usize,
+    // Per-stage concurrency caps (None = unlimited)
+    pub max_downloads: Option<usize>,
+    pub max_whisper: Option<usize>,
+    pub max_ffmpeg: Option<usize>,
+    pub max_opencode: Option<usize>,
+    // SQLite database holding jobs and their replies
+    pub db_path: PathBuf,
+    // How long a summary may be reused for the same video (None = never)
+    pub cache_ttl: Option<Duration>,
+    // Mention how many others sent a cached video today
+    pub repeat_note: bool,
+    // How a message with several videos is answered
+    pub multi_reply: MultiReply,
+    // Platform names to recognize links for (empty = all of them)
+    pub platforms: Vec<String>,
+    pub timeouts: Timeouts,
+    // Retries for the flaky stages: yt-dlp downloads and opencode
+    pub download_retry: RetryPolicy,
+    pub opencode_retry: RetryPolicy,
+    // Videos longer or bigger than this are refused before downloading (None = no limit)
+    pub max_duration: Option<Duration>,
+    pub max_filesize: Option<u64>,
+    // How frames are picked for opencode to look at
+    pub frames: Sampling,
+    // Frames whose perceptual hashes differ in at most this many of 64 bits
+    // count as duplicates (None = keep them all)
+    pub dedup_threshold: Option<u32>,
+    // Tile frames into contact sheets for opencode and the reply
+    pub sheets: Sheets,
+    // Subtitle languages to ask yt-dlp for, most preferred first
+    pub sub_languages: Vec<String>,
+    // Language summaries are written in (None = whatever the prompt yields),
+    // overridden per phone number / group id by `recipient_languages`
+    pub reply_language: Option<String>,
+    pub recipient_languages: Vec<(String, String)>,
+}
+
+// How long each pipeline stage, and a job as a whole, may take
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeouts {
+    pub download: Duration,
+    pub whisper: Duration,
+    pub ffmpeg: Duration,
+    pub opencode: Duration,
+    pub job: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            download: Duration::from_secs(5 * 60),
+            whisper: Duration::from_secs(10 * 60),
+            ffmpeg: Duration::from_secs(2 * 60),
+            opencode: Duration::from_secs(5 * 60),
+            job: Duration::from_secs(20 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MultiReply {
+    // One numbered reply once every video is done
+    #[default]
+    Combined,
+    // One reply per video, as each finishes
+    Separate,
+}
+
+// How we talk to signal-cli
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Transport {
+    // Spawn our own `signal-cli --output=json jsonRpc` child
+    #[default]
+    Spawn,
+    // Connect to `signal-cli daemon --socket <path>`
+    Unix(PathBuf),
+    // Connect to `signal-cli daemon --tcp <host:port>`
+    Tcp(String),
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self> {
+        let transport = match env::var("SIGNAL_CLI_TRANSPORT") {
+            Ok(value) => Transport::parse(&value).context("Invalid SIGNAL_CLI_TRANSPORT")?,
+            Err(_) => Transport::Spawn,
+        };
+
+        let enabled_groups = env::var("BRAINROT_GROUPS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|id| !id.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let attachments_dir = match env::var("SIGNAL_CLI_ATTACHMENTS_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => default_data_dir().join("signal-cli").join("attachments"),
+        };
+
+        let work_root = env::var("BRAINROT_WORK_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("/tmp/brainrot_summarizer"));
+
+        let retention = match env::var("BRAINROT_KEEP_WORKSPACES") {
+            Ok(value) => Retention::parse(&value).context("Invalid BRAINROT_KEEP_WORKSPACES")?,
+            Err(_) => Retention::default(),
+        };
+
+        let multi_reply = match env::var("BRAINROT_MULTI_REPLY") {
+            Ok(value) => MultiReply::parse(&value).context("Invalid BRAINROT_MULTI_REPLY")?,
+            Err(_) => MultiReply::default(),
+        };
+
+        let platforms: Vec<String> = env::var("BRAINROT_PLATFORMS")
+            .map(|value| {
+                value
+                    .split(',')
+                    .map(|name| name.trim().to_lowercase())
+                    .filter(|name| !name.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+        Platforms::select(&platforms).context("Invalid BRAINROT_PLATFORMS")?;
+
+        let strategy = Strategy::parse(&env::var("BRAINROT_FRAME_STRATEGY").unwrap_or_default())
+            .context("Invalid BRAINROT_FRAME_STRATEGY")?;
+        let sampling = Sampling::default();
+        let frames = Sampling {
+            strategy,
+            min_frames: env_usize("BRAINROT_FRAMES_MIN")?.unwrap_or(sampling.min_frames),
+            max_frames: env_usize("BRAINROT_FRAMES_MAX")?.unwrap_or(sampling.max_frames),
+            per_minute: env_f64("BRAINROT_FRAMES_PER_MINUTE")?.unwrap_or(sampling.per_minute),
+            scene_threshold: env_f64("BRAINROT_SCENE_THRESHOLD")?
+                .unwrap_or(sampling.scene_threshold),
+            fixed_fps: env_f64("BRAINROT_FRAME_RATE")?.unwrap_or(sampling.fixed_fps),
+        };
+        if frames.fixed_fps <= 0.0 {
+            bail!("Invalid BRAINROT_FRAME_RATE: must be above 0");
+        }
+
+        let sheet_defaults = Sheets::default();
+        let sheets = Sheets {
+            enabled: env_bool("BRAINROT_CONTACT_SHEETS")?.unwrap_or(sheet_defaults.enabled),
+            columns: env_usize("BRAINROT_SHEET_COLUMNS")?.unwrap_or(sheet_defaults.columns),
+            rows: env_usize("BRAINROT_SHEET_ROWS")?.unwrap_or(sheet_defaults.rows),
+            attach: env_bool("BRAINROT_ATTACH_SHEETS")?.unwrap_or(sheet_defaults.attach),
+        };
+        if sheets.columns == 0 || sheets.rows == 0 {
+            bail!("Invalid BRAINROT_SHEET_COLUMNS/BRAINROT_SHEET_ROWS: must be above 0");
+        }
+
+        let sub_languages = language::parse_list(
+            &env::var("BRAINROT_SUB_LANGS").unwrap_or_else(|_| "en".to_string()),
+        )
+        .context("Invalid BRAINROT_SUB_LANGS")?;
+        let reply_language = match env::var("BRAINROT_REPLY_LANGUAGE") {
+            Ok(value) => language::parse_list(&value)
+                .context("Invalid BRAINROT_REPLY_LANGUAGE")?
+                .into_iter()
+                .next(),
+            Err(_) => None,
+        };
+        let recipient_languages = match env::var("BRAINROT_RECIPIENT_LANGUAGES") {
+            Ok(value) => {
+                parse_recipient_languages(&value).context("Invalid BRAINROT_RECIPIENT_LANGUAGES")?
+            }
+            Err(_) => Vec::new(),
+        };
+
+        let defaults = Timeouts::default();
+        let base_delay = env_secs("BRAINROT_RETRY_BASE_DELAY")?.unwrap_or(Duration::from_secs(2));
+        let max_delay = env_secs("BRAINROT_RETRY_MAX_DELAY")?.unwrap_or(Duration::from_secs(30));
+        let retry_on = |name: &str, default: &str| {
+            RetryPolicy::parse_kinds(&env::var(name).unwrap_or_else(|_| default.to_string()))
+                .with_context(|| format!("Invalid {}", name))
+        };
+        Ok(Self {
+            transport,
+            enabled_groups,
+            attachments_dir,
+            work_root,
+            retention,
+            workers: env_usize("BRAINROT_WORKERS")?.unwrap_or(2),
+            max_queue: env_usize("BRAINROT_MAX_QUEUE")?.unwrap_or(10),
+            max_downloads: env_usize("BRAINROT_MAX_DOWNLOADS")?,
+            // whisper eats every core it can get, so one at a time by default
+            max_whisper: Some(env_usize("BRAINROT_MAX_WHISPER")?.unwrap_or(1)),
+            max_ffmpeg: env_usize("BRAINROT_MAX_FFMPEG")?,
+            max_opencode: env_usize("BRAINROT_MAX_OPENCODE")?,
+            db_path: env::var("BRAINROT_DB")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| default_data_dir().join("brainrot").join("brainrot.db")),
+            cache_ttl: match env_usize("BRAINROT_CACHE_TTL_HOURS")?.unwrap_or(24) {
+                0 => None,
+                hours => Some(Duration::from_secs(hours as u64 * 60 * 60)),
+            },
+            repeat_note: env_bool("BRAINROT_REPEAT_NOTE")?.unwrap_or(true),
+            multi_reply,
+            platforms,
+            timeouts: Timeouts {
+                download: env_secs("BRAINROT_TIMEOUT_DOWNLOAD")?.unwrap_or(defaults.download),
+                whisper: env_secs("BRAINROT_TIMEOUT_WHISPER")?.unwrap_or(defaults.whisper),
+                ffmpeg: env_secs("BRAINROT_TIMEOUT_FFMPEG")?.unwrap_or(defaults.ffmpeg),
+                opencode: env_secs("BRAINROT_TIMEOUT_OPENCODE")?.unwrap_or(defaults.opencode),
+                job: env_secs("BRAINROT_JOB_TIMEOUT")?.unwrap_or(defaults.job),
+            },
+            download_retry: RetryPolicy {
+                max_attempts: env_usize("BRAINROT_RETRY_DOWNLOAD_ATTEMPTS")?.unwrap_or(3) as u32,
+                base_delay,
+                max_delay,
+                retry_on: retry_on("BRAINROT_RETRY_DOWNLOAD_ON", "download_blocked,timeout")?,
+            },
+            opencode_retry: RetryPolicy {
+                max_attempts: env_usize("BRAINROT_RETRY_OPENCODE_ATTEMPTS")?.unwrap_or(2) as u32,
+                base_delay,
+                max_delay,
+                retry_on: retry_on("BRAINROT_RETRY_OPENCODE_ON", "llm_failed,timeout")?,
+            },
+            max_duration: match env_secs("BRAINROT_MAX_DURATION")?
+                .unwrap_or(Duration::from_secs(600))
+            {
+                Duration::ZERO => None,
+                limit => Some(limit),
+            },
+            max_filesize: match env_usize("BRAINROT_MAX_FILESIZE_MB")?.unwrap_or(200) {
+                0 => None,
+                mb => Some(mb as u64 * 1_000_000),
+            },
+            frames,
+            dedup_threshold: match env_usize("BRAINROT_DEDUP_THRESHOLD")?.unwrap_or(6) {
+                0 => None,
+                bits => Some(bits as u32),
+            },
+            sheets,
+            sub_languages,
+            reply_language,
+            recipient_languages,
+        })
+    }
+
+    // What language a summary for `target` should be written in
+    pub fn reply_language_for(&self, target: &ReplyTarget) -> Option<String> {
+        let (ReplyTarget::Direct(id) | ReplyTarget::Group(id)) = target;
+        self.recipient_languages
+            .iter()
+            .find(|(recipient, _)| recipient == id)
+            .map(|(_, language)| language.clone())
+            .or_else(|| self.reply_language.clone())
+    }
+
+    // Contact sheets waiting to be sent with their reply
+    pub fn previews_dir(&self) -> PathBuf {
+        self.work_root.join("previews")
+    }
+
+    pub fn group_enabled(&self, group_id: &str) -> bool {
+        self.enabled_groups.iter().any(|id| id == group_id)
+    }
+}
+
+impl Transport {
+    // Accepts "spawn", "unix:<path>" or "tcp:<host:port>"
+    pub fn parse(value: &str) -> Result<Self> {
+        let value = value.trim();
+        if value.is_empty() || value == "spawn" {
+            return Ok(Transport::Spawn);
+        }
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(Transport::Unix(PathBuf::from(path)));
+        }
+        if let Some(addr) = value.strip_prefix("tcp:") {
+            return Ok(Transport::Tcp(addr.to_string()));
+        }
+        bail!(
+            "expected 'spawn', 'unix:<path>' or 'tcp:<host:port>', got '{}'",
+            value
+        )
+    }
+}
+
+impl MultiReply {
+    // Accepts "combined" or "separate"
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "" | "combined" => Ok(MultiReply::Combined),
+            "separate" => Ok(MultiReply::Separate),
+            other => bail!("expected 'combined' or 'separate', got '{}'", other),
+        }
+    }
+}
+
+// Accepts "<phone number or group id>=<language>,..."
+fn parse_recipient_languages(value: &str) -> Result<Vec<(String, String)>> {
+    let mut languages = Vec::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let Some((recipient, code)) = entry.rsplit_once('=') else {
+            bail!("expected '<recipient>=<language>', got '{}'", entry);
+        };
+        let Some(code) = language::parse_list(code)?.into_iter().next() else {
+            bail!("no language given for '{}'", recipient);
+        };
+        languages.push((recipient.trim().to_string(), code));
+    }
+    Ok(languages)
+}
+
+// $XDG_DATA_HOME, falling back to ~/.local/share like signal-cli does
+fn default_data_dir() -> PathBuf {
+    if let Ok(dir) = env::var("XDG_DATA_HOME") {
+        return PathBuf::from(dir);
+    }
+    let home = env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".local").join("share")
+}
+
+fn env_usize(name: &str) -> Result<Option<usize>> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid {}", name)),
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_f64(name: &str) -> Result<Option<f64>> {
+    match env::var(name) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid {}", name)),
+        Err(_) => Ok(None),
+    }
+}
+
+// A number of seconds
+fn env_secs(name: &str) -> Result<Option<Duration>> {
+    Ok(env_usize(name)?.map(|secs| Duration::from_secs(secs as u64)))
+}
+
+// Accepts 1/0, true/false, yes/no, on/off
+fn env_bool(name: &str) -> Result<Option<bool>> {
+    match env::var(name) {
+        Ok(value) => match value.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Some(true)),
+            "0" | "false" | "no" | "off" => Ok(Some(false)),
+            other => bail!("Invalid {}: expected on or off, got '{}'", name, other),
+        },
+        Err(_) => Ok(None),
+    }
+}
@@ -0,0 +1,58 @@
+use serde::Deserialize;
+
+// --- Signal Envelope Structs ---
+// The parts of signal-cli's `receive` notifications the bot cares about.
+#[derive(Deserialize, Debug)]
+pub struct Envelope {
+    #[serde(rename = "sourceNumber")]
+    pub source_number: Option<String>,
+    #[serde(rename = "sourceUuid")]
+    pub source_uuid: Option<String>,
+    // Sent timestamp of the message, which is also its id for quotes and reactions
+    pub timestamp: Option<u64>,
+    #[serde(rename = "dataMessage")]
+    pub data_message: Option<DataMessage>,
+    #[serde(rename = "syncMessage")]
+    pub sync_message: Option<SyncMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DataMessage {
+    pub message: Option<String>,
+    #[serde(rename = "groupInfo")]
+    pub group_info: Option<GroupInfo>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GroupInfo {
+    #[serde(rename = "groupId")]
+    pub group_id: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SyncMessage {
+    #[serde(rename = "sentMessage")]
+    pub sent_message: Option<SentMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SentMessage {
+    pub destination: Option<String>,
+    pub message: Option<String>,
+    #[serde(rename = "groupInfo")]
+    pub group_info: Option<GroupInfo>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Attachment {
+    #[serde(rename = "contentType")]
+    pub content_type: Option<String>,
+    // File name inside signal-cli's attachment store
+    pub id: Option<String>,
+    // Original file name as sent, if the client shared it
+    pub filename: Option<String>,
+}
@@ -0,0 +1,237 @@
+use std::fmt;
+
+// Why an analysis failed. The payload is the raw detail (usually a tool's
+// stderr) for the logs; senders only ever see `user_message`.
+#[derive(Debug)]
+pub enum AnalysisError {
+    // The platform refused us: 403s, rate limits, IP blocks
+    DownloadBlocked(String),
+    // Private, age-restricted, login-only or deleted videos
+    Unavailable(String),
+    // The link worked but there was no video behind it
+    NoVideo(String),
+    TranscriptionFailed(String),
+    LlmFailed(String),
+    Timeout(String),
+    // Over the duration/size limits. Unlike the others, the payload is meant
+    // for the sender: it says what the limit is.
+    TooBig(String),
+    // Live streams and premieres that haven't happened yet
+    Live(String),
+    // Anything else (ffmpeg, file system, ...)
+    Internal(String),
+}
+
+impl AnalysisError {
+    // Names for `kind`, as used in config
+    pub const KINDS: &[&str] = &[
+        "download_blocked",
+        "unavailable",
+        "no_video",
+        "transcription_failed",
+        "llm_failed",
+        "timeout",
+        "too_big",
+        "live",
+        "internal",
+    ];
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnalysisError::DownloadBlocked(_) => "download_blocked",
+            AnalysisError::Unavailable(_) => "unavailable",
+            AnalysisError::NoVideo(_) => "no_video",
+            AnalysisError::TranscriptionFailed(_) => "transcription_failed",
+            AnalysisError::LlmFailed(_) => "llm_failed",
+            AnalysisError::Timeout(_) => "timeout",
+            AnalysisError::TooBig(_) => "too_big",
+            AnalysisError::Live(_) => "live",
+            AnalysisError::Internal(_) => "internal",
+        }
+    }
+
+    pub fn user_message(&self) -> String {
+        let message = match self {
+            AnalysisError::DownloadBlocked(_) => {
+                "😵 Couldn't download that video right now, the site is blocking me. Try again later."
+            }
+            AnalysisError::Unavailable(_) => {
+                "🔒 That video is private, age-restricted or deleted, so I can't watch it."
+            }
+            AnalysisError::NoVideo(_) => "🤷 I couldn't find a video behind that link.",
+            AnalysisError::TranscriptionFailed(_) => {
+                "🎙️ I couldn't transcribe the audio of that video."
+            }
+            AnalysisError::LlmFailed(_) => {
+                "🧠 The AI summarizer failed on that one. Try sending it again in a bit."
+            }
+            AnalysisError::Timeout(_) => "⏱️ That video took too long to process, so I gave up.",
+            AnalysisError::TooBig(reason) => {
+                return format!("📏 That one's too much for me: {}.", reason);
+            }
+            AnalysisError::Live(_) => "🔴 That's a live stream, send it again once it has ended.",
+            AnalysisError::Internal(_) => "💥 Something went wrong while analyzing that video.",
+        };
+        message.to_string()
+    }
+
+    // Sort a failed yt-dlp run into the right bucket based on its stderr
+    pub fn from_ytdlp(stderr: &str) -> Self {
+        let lower = stderr.to_lowercase();
+        let detail = stderr.trim().to_string();
+
+        let matches = |needles: &[&str]| needles.iter().any(|n| lower.contains(n));
+        // Blocks first: rate limits and bot checks often come dressed up as
+        // "not available" or "sign in", but are worth retrying
+        if matches(&[
+            "http error 403",
+            "http error 429",
+            "ip address is blocked",
+            "rate-limit",
+            "rate limit",
+            "too many requests",
+            "not a bot",
+        ]) {
+            AnalysisError::DownloadBlocked(detail)
+        } else if matches(&[
+            "private",
+            "age-restricted",
+            "age restricted",
+            "confirm your age",
+            "sign in",
+            "login required",
+            "log in",
+            "not available",
+            "unavailable",
+            "removed",
+        ]) {
+            AnalysisError::Unavailable(detail)
+        } else if matches(&["timed out", "timeout"]) {
+            AnalysisError::Timeout(detail)
+        } else if matches(&["unsupported url", "no video", "no media", "http error 404"]) {
+            AnalysisError::NoVideo(detail)
+        } else {
+            AnalysisError::Internal(detail)
+        }
+    }
+}
+
+impl fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisError::DownloadBlocked(d) => write!(f, "download blocked: {}", d),
+            AnalysisError::Unavailable(d) => write!(f, "video unavailable: {}", d),
+            AnalysisError::NoVideo(d) => write!(f, "no video found: {}", d),
+            AnalysisError::TranscriptionFailed(d) => write!(f, "transcription failed: {}", d),
+            AnalysisError::LlmFailed(d) => write!(f, "opencode failed: {}", d),
+            AnalysisError::Timeout(d) => write!(f, "timed out: {}", d),
+            AnalysisError::TooBig(d) => write!(f, "over the limits: {}", d),
+            AnalysisError::Live(d) => write!(f, "live stream: {}", d),
+            AnalysisError::Internal(d) => write!(f, "{}", d),
+        }
+    }
+}
+
+impl std::error::Error for AnalysisError {}
+
+impl From<anyhow::Error> for AnalysisError {
+    fn from(e: anyhow::Error) -> Self {
+        AnalysisError::Internal(format!("{:#}", e))
+    }
+}
+
+impl From<std::io::Error> for AnalysisError {
+    fn from(e: std::io::Error) -> Self {
+        AnalysisError::Internal(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kind(stderr: &str) -> &'static str {
+        AnalysisError::from_ytdlp(stderr).kind()
+    }
+
+    // Real yt-dlp stderr
+    #[test]
+    fn classifies_ytdlp_errors() {
+        assert_eq!(
+            kind(
+                "ERROR: [Instagram] C2AbCdEfGhI: Requested content is not available, \
+                 rate-limit reached or login required. Use --cookies, --cookies-from-browser, \
+                 --username and --password, --netrc-cmd, or --netrc (instagram) to provide \
+                 account credentials"
+            ),
+            "download_blocked"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [youtube] dQw4w9WgXcQ: Sign in to confirm you\u{2019}re not a bot. \
+                 Use --cookies-from-browser or --cookies for the authentication."
+            ),
+            "download_blocked"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [TikTok] 7301234567890123456: Unable to download webpage: \
+                 HTTP Error 429: Too Many Requests (caused by <HTTPError 429: Too Many Requests>)"
+            ),
+            "download_blocked"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [TikTok] 7301234567890123456: Your IP address is blocked from \
+                 accessing this post"
+            ),
+            "download_blocked"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [youtube] Xy12AbCdEfG: Private video. Sign in if you've been \
+                 granted access to this video"
+            ),
+            "unavailable"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [youtube] Xy12AbCdEfG: Sign in to confirm your age. This video may \
+                 be inappropriate for some users."
+            ),
+            "unavailable"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [youtube] Xy12AbCdEfG: Video unavailable. This video contains content \
+                 from SomeLabel, who has blocked it on copyright grounds"
+            ),
+            "unavailable"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [generic] Unable to download webpage: HTTP Error 404: Not Found \
+                 (caused by <HTTPError 404: Not Found>)"
+            ),
+            "no_video"
+        );
+        assert_eq!(
+            kind("ERROR: Unsupported URL: https://example.com/"),
+            "no_video"
+        );
+        assert_eq!(
+            kind(
+                "ERROR: [TikTok] 7301234567890124040: Unable to download webpage: \
+                 The read operation timed out"
+            ),
+            "timeout"
+        );
+        // A 404 in the video id is not a 404
+        assert_eq!(
+            kind(
+                "ERROR: [TikTok] 7301404404404404404: Unable to extract universal data for rehydration"
+            ),
+            "internal"
+        );
+    }
+}
@@ -0,0 +1,604 @@
+use crate::error::AnalysisError;
+use crate::pipeline::output_within;
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::process::Command;
+
+// ffmpeg's showinfo filter logs one line per frame it lets through
+static PTS_TIME_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"showinfo.*\bpts_time:\s*([0-9.]+)").unwrap());
+
+// How frames are picked out of a video
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Strategy {
+    // A frame whenever the picture changes a lot, or evenly spaced ones
+    // (see `Budget`) when the video has too few cuts for that
+    #[default]
+    Scene,
+    // Evenly spaced frames, as many as the video's length is worth
+    Budget,
+    // One frame every 1/fps seconds, however long the video is
+    Fixed,
+}
+
+impl Strategy {
+    // Accepts "scene", "budget" or "fixed"
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "" | "scene" => Ok(Strategy::Scene),
+            "budget" => Ok(Strategy::Budget),
+            "fixed" => Ok(Strategy::Fixed),
+            other => bail!("expected 'scene', 'budget' or 'fixed', got '{}'", other),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sampling {
+    pub strategy: Strategy,
+    // Bounds on how many frames opencode gets to look at. Fixed-rate
+    // sampling, the fallback for videos of unknown length, only obeys `max`.
+    pub min_frames: usize,
+    pub max_frames: usize,
+    // Frame budget per minute of video
+    pub per_minute: f64,
+    // How different a frame must be from the one before to count as a new
+    // scene, from 0 (any change) to 1 (a completely different picture)
+    pub scene_threshold: f64,
+    pub fixed_fps: f64,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Self {
+            strategy: Strategy::Scene,
+            min_frames: 6,
+            max_frames: 30,
+            per_minute: 20.0,
+            scene_threshold: 0.3,
+            fixed_fps: 0.5,
+        }
+    }
+}
+
+impl Sampling {
+    // Frames worth spending on a video this long
+    fn budget(&self, duration: f64) -> usize {
+        let frames = (duration / 60.0 * self.per_minute).round() as usize;
+        frames.clamp(self.min_frames, self.max_frames.max(self.min_frames))
+    }
+}
+
+// An extracted frame and where in the video it was taken
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub path: PathBuf,
+    // Seconds from the start, when ffmpeg told us
+    pub timestamp: Option<f64>,
+}
+
+// Fill `frames/` with frame_001.jpg onwards according to `sampling`
+pub async fn extract(
+    work_dir: &Path,
+    video_path: &Path,
+    sampling: &Sampling,
+    limit: Duration,
+) -> Result<Vec<Frame>, AnalysisError> {
+    let frames_dir = work_dir.join("frames");
+    fs::create_dir_all(&frames_dir).context("Failed to create frames directory")?;
+
+    let duration = match probe_duration(video_path, limit).await {
+        Ok(duration) => Some(duration),
+        Err(e) => {
+            eprintln!("Warning: {:#}", e);
+            None
+        }
+    };
+    let strategy = match (sampling.strategy, duration) {
+        (Strategy::Fixed, _) | (_, None) => Strategy::Fixed,
+        (strategy, Some(_)) => strategy,
+    };
+    if strategy != sampling.strategy {
+        println!(
+            "🎞️ Video length unknown, falling back to fixed-rate sampling at {} fps",
+            sampling.fixed_fps
+        );
+    }
+
+    let mut frames = match (strategy, duration) {
+        (Strategy::Scene, Some(duration)) => {
+            let filter = format!(
+                "select='eq(n,0)+gt(scene,{})',showinfo",
+                sampling.scene_threshold
+            );
+            let frames = run_ffmpeg(work_dir, video_path, &filter, None, limit).await?;
+            if frames.len() >= sampling.min_frames {
+                println!(
+                    "🎞️ Scene sampling (threshold {}) found {} frames in {:.1}s",
+                    sampling.scene_threshold,
+                    frames.len(),
+                    duration
+                );
+                frames
+            } else {
+                println!(
+                    "🎞️ Scene sampling found only {} frames in {:.1}s, using an even budget instead",
+                    frames.len(),
+                    duration
+                );
+                clear(&frames)?;
+                sample_budget(work_dir, video_path, sampling, duration, limit).await?
+            }
+        }
+        (Strategy::Budget, Some(duration)) => {
+            sample_budget(work_dir, video_path, sampling, duration, limit).await?
+        }
+        _ => {
+            let filter = format!("fps={},showinfo", sampling.fixed_fps);
+            let frames = run_ffmpeg(
+                work_dir,
+                video_path,
+                &filter,
+                Some(sampling.max_frames),
+                limit,
+            )
+            .await?;
+            println!(
+                "🎞️ Fixed-rate sampling at {} fps took {} frames",
+                sampling.fixed_fps,
+                frames.len()
+            );
+            frames
+        }
+    };
+
+    if frames.len() > sampling.max_frames {
+        let before = frames.len();
+        frames = thin(frames, sampling.max_frames)?;
+        println!("🎞️ Thinned {} frames down to {}", before, frames.len());
+    }
+    if frames.is_empty() {
+        return Err(AnalysisError::NoVideo(
+            "ffmpeg extracted no frames".to_string(),
+        ));
+    }
+    Ok(frames)
+}
+
+async fn sample_budget(
+    work_dir: &Path,
+    video_path: &Path,
+    sampling: &Sampling,
+    duration: f64,
+    limit: Duration,
+) -> Result<Vec<Frame>, AnalysisError> {
+    let budget = sampling.budget(duration);
+    let filter = format!("fps={},showinfo", budget as f64 / duration.max(0.1));
+    let frames = run_ffmpeg(work_dir, video_path, &filter, Some(budget), limit).await?;
+    println!(
+        "🎞️ Budget sampling took {} of {} frames over {:.1}s",
+        frames.len(),
+        budget,
+        duration
+    );
+    Ok(frames)
+}
+
+// Run ffmpeg with a video filter ending in showinfo and pair the frames it
+// wrote with the timestamps it logged
+async fn run_ffmpeg(
+    work_dir: &Path,
+    video_path: &Path,
+    filter: &str,
+    max_frames: Option<usize>,
+    limit: Duration,
+) -> Result<Vec<Frame>, AnalysisError> {
+    println!("[DEBUG] ffmpeg -vf {}", filter);
+    let mut command = Command::new("ffmpeg");
+    command
+        .current_dir(work_dir)
+        .arg("-i")
+        .arg(video_path)
+        .args(["-vf", filter, "-fps_mode", "vfr"]);
+    if let Some(max) = max_frames {
+        command.args(["-frames:v", &max.to_string()]);
+    }
+    command
+        .arg("frames/frame_%03d.jpg")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = output_within(&mut command, "ffmpeg", limit)
+        .await?
+        .context("Failed to run ffmpeg")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        return Err(AnalysisError::Internal(format!(
+            "ffmpeg failed: {}",
+            stderr
+        )));
+    }
+
+    let paths = list(&work_dir.join("frames"))?;
+    let timestamps = timestamps(&stderr);
+    if timestamps.len() != paths.len() {
+        eprintln!(
+            "Warning: ffmpeg wrote {} frames but logged {} timestamps",
+            paths.len(),
+            timestamps.len()
+        );
+    }
+    Ok(paths
+        .into_iter()
+        .enumerate()
+        .map(|(i, path)| Frame {
+            path,
+            timestamp: timestamps.get(i).copied(),
+        })
+        .collect())
+}
+
+async fn probe_duration(video_path: &Path, limit: Duration) -> Result<f64> {
+    let mut command = Command::new("ffprobe");
+    command
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(video_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = output_within(&mut command, "ffprobe", limit)
+        .await?
+        .context("Failed to run ffprobe")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.trim().parse::<f64>() {
+        Ok(duration) if duration > 0.0 => Ok(duration),
+        _ => bail!("ffprobe reported no duration: {}", stdout.trim()),
+    }
+}
+
+fn timestamps(ffmpeg_log: &str) -> Vec<f64> {
+    PTS_TIME_REGEX
+        .captures_iter(ffmpeg_log)
+        .filter_map(|c| c[1].parse().ok())
+        .collect()
+}
+
+// frame_*.jpg in `dir`, in order
+fn list(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with("frame_") && n.ends_with(".jpg"))
+        })
+        .collect();
+    // frame_999.jpg sorts after frame_1000.jpg by name alone
+    paths.sort_by_key(|p| (p.as_os_str().len(), p.clone()));
+    Ok(paths)
+}
+
+fn clear(frames: &[Frame]) -> Result<()> {
+    for frame in frames {
+        fs::remove_file(&frame.path)?;
+    }
+    Ok(())
+}
+
+// Keep `max` evenly spread frames, always including the first and last
+fn pick_evenly(len: usize, max: usize) -> Vec<usize> {
+    if len <= max {
+        return (0..len).collect();
+    }
+    if max <= 1 {
+        return (0..max).collect();
+    }
+    (0..max)
+        .map(|i| (i * (len - 1) + (max - 1) / 2) / (max - 1))
+        .collect()
+}
+
+fn thin(frames: Vec<Frame>, max: usize) -> Result<Vec<Frame>> {
+    let keep = pick_evenly(frames.len(), max);
+    keep_only(frames, &keep)
+}
+
+// Delete every frame not listed in `keep` and renumber the rest
+fn keep_only(frames: Vec<Frame>, keep: &[usize]) -> Result<Vec<Frame>> {
+    let (kept, dropped): (Vec<_>, Vec<_>) = frames
+        .into_iter()
+        .enumerate()
+        .partition(|(i, _)| keep.contains(i));
+    clear(&dropped.into_iter().map(|(_, f)| f).collect::<Vec<_>>())?;
+    renumber(kept.into_iter().map(|(_, f)| f).collect())
+}
+
+// Rename frames to frame_001.jpg onwards, in the order given
+fn renumber(frames: Vec<Frame>) -> Result<Vec<Frame>> {
+    let mut renamed = Vec::with_capacity(frames.len());
+    for (i, frame) in frames.into_iter().enumerate() {
+        let path = frame.path.with_file_name(format!("frame_{:03}.jpg", i + 1));
+        if path != frame.path {
+            fs::rename(&frame.path, &path)
+                .with_context(|| format!("Failed to rename {}", frame.path.display()))?;
+        }
+        renamed.push(Frame { path, ..frame });
+    }
+    Ok(renamed)
+}
+
+// Side of the grid frames are shrunk to for hashing: 9x8 pixels give 8x8
+// left-right gradients, one bit each
+const HASH_WIDTH: usize = 9;
+const HASH_HEIGHT: usize = 8;
+
+// One line of frames/index.json
+#[derive(Debug, Serialize)]
+struct IndexEntry {
+    file: String,
+    // Seconds from the start of the video
+    timestamp: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+struct Index {
+    frames: Vec<IndexEntry>,
+    // Near-identical frames removed by `dedup`
+    duplicates_dropped: usize,
+}
+
+// Drop frames that look like one we already kept, judged by the Hamming
+// distance between their perceptual hashes (None skips the comparison),
+// renumber the survivors and list them with their timestamps in
+// frames/index.json. Hashing trouble only costs us the dedup, not the job.
+pub async fn dedup(
+    work_dir: &Path,
+    frames: Vec<Frame>,
+    threshold: Option<u32>,
+    limit: Duration,
+) -> Result<Vec<Frame>, AnalysisError> {
+    let before = frames.len();
+    let frames = match threshold {
+        Some(threshold) if before > 1 => match hash_frames(work_dir, before, limit).await {
+            Ok(hashes) => keep_only(frames, &distinct(&hashes, threshold))?,
+            Err(e) => {
+                eprintln!("Warning: Skipping frame dedup: {:#}", e);
+                frames
+            }
+        },
+        _ => frames,
+    };
+
+    let dropped = before - frames.len();
+    if dropped > 0 {
+        println!(
+            "🧹 Dropped {} near-duplicate frames, {} left",
+            dropped,
+            frames.len()
+        );
+    }
+    write_index(work_dir, &frames, dropped)?;
+    Ok(frames)
+}
+
+// dHash every frame in frames/ with a single ffmpeg run, which shrinks them
+// to 9x8 grayscale and hands us the raw pixels
+async fn hash_frames(work_dir: &Path, count: usize, limit: Duration) -> Result<Vec<u64>> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .current_dir(work_dir)
+        .args([
+            "-v",
+            "error",
+            "-i",
+            "frames/frame_%03d.jpg",
+            "-vf",
+            &format!("scale={}:{},format=gray", HASH_WIDTH, HASH_HEIGHT),
+            "-f",
+            "rawvideo",
+            "-",
+        ])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = output_within(&mut command, "ffmpeg", limit)
+        .await?
+        .context("Failed to run ffmpeg")?;
+    if !output.status.success() {
+        bail!("ffmpeg failed: {}", String::from_utf8_lossy(&output.stderr));
+    }
+
+    let hashes: Vec<u64> = output
+        .stdout
+        .chunks_exact(HASH_WIDTH * HASH_HEIGHT)
+        .map(dhash)
+        .collect();
+    if hashes.len() != count {
+        bail!("hashed {} frames, expected {}", hashes.len(), count);
+    }
+    Ok(hashes)
+}
+
+// Difference hash: a bit per pixel pair, set where it gets brighter to the
+// right. Survives recompression and small shifts, unlike a checksum.
+fn dhash(pixels: &[u8]) -> u64 {
+    let mut hash = 0u64;
+    for row in pixels.chunks_exact(HASH_WIDTH) {
+        for pair in row.windows(2) {
+            hash = hash << 1 | u64::from(pair[1] > pair[0]);
+        }
+    }
+    hash
+}
+
+// Indices of the frames to keep: each one further than `threshold` bits
+// from every frame kept before it
+fn distinct(hashes: &[u64], threshold: u32) -> Vec<usize> {
+    let mut kept: Vec<usize> = Vec::new();
+    for (i, hash) in hashes.iter().enumerate() {
+        if kept
+            .iter()
+            .all(|&k| (hashes[k] ^ hash).count_ones() > threshold)
+        {
+            kept.push(i);
+        }
+    }
+    kept
+}
+
+fn write_index(work_dir: &Path, frames: &[Frame], dropped: usize) -> Result<()> {
+    let index = Index {
+        frames: frames
+            .iter()
+            .map(|frame| IndexEntry {
+                file: frame
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                timestamp: frame.timestamp.map(|t| (t * 100.0).round() / 100.0),
+            })
+            .collect(),
+        duplicates_dropped: dropped,
+    };
+    let json = serde_json::to_string_pretty(&index)?;
+    fs::write(work_dir.join("frames").join("index.json"), json)
+        .context("Failed to write frames/index.json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_the_budget_with_duration() {
+        let sampling = Sampling::default();
+        // A 9 second TikTok still gets a handful of frames
+        assert_eq!(sampling.budget(9.0), 6);
+        assert_eq!(sampling.budget(45.0), 15);
+        // and a 3 minute video doesn't flood opencode
+        assert_eq!(sampling.budget(180.0), 30);
+    }
+
+    #[test]
+    fn reads_timestamps_from_showinfo() {
+        let log = "\
+[Parsed_showinfo_1 @ 0x5581] config in time_base: 1/15360, frame_rate: 30/1
+[Parsed_showinfo_1 @ 0x5581] n:   0 pts:      0 pts_time:0       duration:512 fmt:yuv420p
+[Parsed_showinfo_1 @ 0x5581] n:   1 pts:  66560 pts_time:4.33333 duration:512 fmt:yuv420p
+[out#0/image2 @ 0x5590] video:210KiB audio:0KiB pts_time:9.1
+[Parsed_showinfo_1 @ 0x5581] n:   2 pts: 130048 pts_time:8.46667 duration:512 fmt:yuv420p
+";
+        assert_eq!(timestamps(log), [0.0, 4.33333, 8.46667]);
+    }
+
+    #[test]
+    fn thins_evenly_keeping_both_ends() {
+        assert_eq!(pick_evenly(4, 10), [0, 1, 2, 3]);
+        assert_eq!(pick_evenly(10, 4), [0, 3, 6, 9]);
+        assert_eq!(pick_evenly(90, 30).len(), 30);
+        assert_eq!(pick_evenly(90, 30).last(), Some(&89));
+    }
+
+    #[test]
+    fn thins_and_renumbers_frames_on_disk() {
+        let dir = std::env::temp_dir().join(format!("brainrot-frames-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let frames: Vec<Frame> = (1..=5)
+            .map(|i| {
+                let path = dir.join(format!("frame_{:03}.jpg", i));
+                fs::write(&path, i.to_string()).unwrap();
+                Frame {
+                    path,
+                    timestamp: Some(i as f64),
+                }
+            })
+            .collect();
+
+        let kept = thin(frames, 3).unwrap();
+        let on_disk: Vec<String> = list(&dir)
+            .unwrap()
+            .iter()
+            .map(|p| fs::read_to_string(p).unwrap())
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(on_disk, ["1", "3", "5"]);
+        let timestamps: Vec<_> = kept.iter().map(|f| f.timestamp).collect();
+        assert_eq!(timestamps, [Some(1.0), Some(3.0), Some(5.0)]);
+        assert!(kept[2].path.ends_with("frame_003.jpg"));
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(Strategy::parse("").unwrap(), Strategy::Scene);
+        assert_eq!(Strategy::parse("budget").unwrap(), Strategy::Budget);
+        assert_eq!(Strategy::parse("fixed").unwrap(), Strategy::Fixed);
+        assert!(Strategy::parse("random").is_err());
+    }
+
+    #[test]
+    fn hashes_gradients_not_brightness() {
+        let ramp: Vec<u8> = (0..72).map(|i| (i % 9 * 20) as u8).collect();
+        assert_eq!(dhash(&ramp), u64::MAX);
+        // The same picture a bit brighter hashes the same
+        let brighter: Vec<u8> = ramp.iter().map(|p| p + 30).collect();
+        assert_eq!(dhash(&brighter), dhash(&ramp));
+        assert_eq!(dhash(&[128; 72]), 0);
+    }
+
+    #[test]
+    fn keeps_frames_unlike_any_kept_before() {
+        let speaker = 0x0f0f_0f0f_0f0f_0f0f;
+        let speaker_nods = speaker ^ 0b101;
+        let broll = !speaker;
+        let hashes = [speaker, speaker_nods, broll, speaker, speaker_nods ^ 0b1000];
+        assert_eq!(distinct(&hashes, 5), [0, 2]);
+        assert_eq!(distinct(&hashes, 1), [0, 1, 2]);
+        assert_eq!(distinct(&hashes, 0), [0, 1, 2, 4]);
+    }
+
+    #[test]
+    fn indexes_survivors_with_their_timestamps() {
+        let dir = std::env::temp_dir().join(format!("brainrot-index-{}", std::process::id()));
+        let frames_dir = dir.join("frames");
+        fs::create_dir_all(&frames_dir).unwrap();
+        let frames: Vec<Frame> = [0.0, 2.0, 4.004, 6.0]
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| {
+                let path = frames_dir.join(format!("frame_{:03}.jpg", i + 1));
+                fs::write(&path, "").unwrap();
+                Frame {
+                    path,
+                    timestamp: Some(t),
+                }
+            })
+            .collect();
+
+        let kept = keep_only(frames, &[0, 2]).unwrap();
+        write_index(&dir, &kept, 2).unwrap();
+        let index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(frames_dir.join("index.json")).unwrap())
+                .unwrap();
+        let remaining = list(&frames_dir).unwrap().len();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(remaining, 2);
+        assert_eq!(index["duplicates_dropped"], 2);
+        assert_eq!(index["frames"][1]["file"], "frame_002.jpg");
+        assert_eq!(index["frames"][1]["timestamp"], 4.0);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{Result, bail};
+
+// Languages we know by more than their code: the ISO 639-1 code we use,
+// the three-letter codes some platforms name their captions with (TikTok's
+// "dan-DK"), and the English name whisper prints. Anything else still works,
+// it just goes by whatever code it was given.
+const LANGUAGES: &[(&str, &[&str], &str)] = &[
+    ("ar", &["ara"], "Arabic"),
+    ("da", &["dan"], "Danish"),
+    ("de", &["deu", "ger"], "German"),
+    ("en", &["eng"], "English"),
+    ("es", &["spa"], "Spanish"),
+    ("fi", &["fin"], "Finnish"),
+    ("fr", &["fra", "fre"], "French"),
+    ("hi", &["hin"], "Hindi"),
+    ("it", &["ita"], "Italian"),
+    ("ja", &["jpn"], "Japanese"),
+    ("ko", &["kor"], "Korean"),
+    ("nl", &["nld", "dut"], "Dutch"),
+    ("no", &["nor", "nob", "nb"], "Norwegian"),
+    ("pl", &["pol"], "Polish"),
+    ("pt", &["por"], "Portuguese"),
+    ("ru", &["rus"], "Russian"),
+    ("sv", &["swe"], "Swedish"),
+    ("tr", &["tur"], "Turkish"),
+    ("uk", &["ukr"], "Ukrainian"),
+    ("zh", &["zho", "chi"], "Chinese"),
+];
+
+// "dan-DK" -> "da", "en-orig" -> "en", "pt-BR" -> "pt", "Danish" -> "da"
+pub fn normalize(code: &str) -> String {
+    let primary = code
+        .trim()
+        .split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|(iso, aliases, name)| {
+            *iso == primary || aliases.contains(&primary.as_str()) || name.to_lowercase() == primary
+        })
+        .map(|(iso, _, _)| iso.to_string())
+        .unwrap_or(primary)
+}
+
+// "da" -> "Danish", for telling the model what to write in
+pub fn name(code: &str) -> String {
+    let code = normalize(code);
+    LANGUAGES
+        .iter()
+        .find(|(iso, _, _)| *iso == code)
+        .map(|(_, _, name)| name.to_string())
+        .unwrap_or(code)
+}
+
+// Accepts a comma-separated list of language codes, most preferred first
+pub fn parse_list(value: &str) -> Result<Vec<String>> {
+    let mut languages: Vec<String> = Vec::new();
+    for code in value.split(',').map(str::trim).filter(|c| !c.is_empty()) {
+        if !code.chars().all(|c| c.is_ascii_alphabetic() || c == '-') {
+            bail!("'{}' is not a language code like 'en' or 'da'", code);
+        }
+        let code = normalize(code);
+        if !languages.contains(&code) {
+            languages.push(code);
+        }
+    }
+    Ok(languages)
+}
+
+// yt-dlp --sub-langs value asking for every spelling of `languages`. yt-dlp
+// matches each entry as a regex against the whole language name.
+pub fn ytdlp_sub_langs(languages: &[String]) -> String {
+    languages
+        .iter()
+        .flat_map(|code| {
+            let aliases = LANGUAGES
+                .iter()
+                .find(|(iso, _, _)| iso == code)
+                .map(|(_, aliases, _)| *aliases)
+                .unwrap_or_default();
+            std::iter::once(code.as_str()).chain(aliases.iter().copied())
+        })
+        .map(|code| format!("{}(-.*)?", code))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_platform_codes_and_names() {
+        assert_eq!(normalize("dan-DK"), "da");
+        assert_eq!(normalize("en-orig"), "en");
+        assert_eq!(normalize("pt-BR"), "pt");
+        assert_eq!(normalize("spa-ES"), "es");
+        assert_eq!(normalize("Danish"), "da");
+        assert_eq!(normalize("is"), "is");
+        assert_eq!(name("spa"), "Spanish");
+        assert_eq!(name("is"), "is");
+    }
+
+    #[test]
+    fn parses_preference_lists() {
+        assert_eq!(parse_list("da, es,en,dan").unwrap(), ["da", "es", "en"]);
+        assert!(parse_list("en;da").is_err());
+    }
+
+    #[test]
+    fn asks_ytdlp_for_every_spelling() {
+        assert_eq!(
+            ytdlp_sub_langs(&["da".to_string(), "is".to_string()]),
+            "da(-.*)?,dan(-.*)?,is(-.*)?"
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use crate::platform::{Platforms, normalize_host};
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::sync::LazyLock;
+use tokio::process::Command;
+use url::Url;
+
+// Short links rarely take more than one or two hops
+const MAX_REDIRECTS: usize = 5;
+const RESOLVE_TIMEOUT_SECS: &str = "10";
+
+static URL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"https?://[^\s<>]+").unwrap());
+
+// A video as the platform knows it: the same clip always ends up with the
+// same platform + id, however the link that pointed at it was written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalVideo {
+    pub platform: String,
+    pub id: String,
+    // Tracking-free link that yt-dlp can download
+    pub url: String,
+}
+
+// A supported link found in a message
+#[derive(Debug, Clone, PartialEq)]
+pub enum Link {
+    Video(CanonicalVideo),
+    // Only says which video it is after following its redirects
+    Short { platform: String, url: String },
+}
+
+impl Link {
+    pub fn platform(&self) -> &str {
+        match self {
+            Link::Video(video) => &video.platform,
+            Link::Short { platform, .. } => platform,
+        }
+    }
+
+    fn same_video(&self, other: &Link) -> bool {
+        match (self, other) {
+            (Link::Video(a), Link::Video(b)) => a.platform == b.platform && a.id == b.id,
+            (Link::Short { url: a, .. }, Link::Short { url: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+// Every link to an enabled platform in `text`, in order of appearance, each
+// video once
+pub fn find(text: &str, platforms: &Platforms) -> Vec<Link> {
+    let mut links: Vec<Link> = Vec::new();
+    for m in URL_REGEX.find_iter(text) {
+        let Some(link) = parse(trim_punctuation(m.as_str()), platforms) else {
+            continue;
+        };
+        if !links.iter().any(|seen| seen.same_video(&link)) {
+            links.push(link);
+        }
+    }
+    links
+}
+
+// "look at this (https://…/video/123)!" must not end in ")!"
+fn trim_punctuation(url: &str) -> &str {
+    url.trim_end_matches([
+        '.', ',', ';', ':', '!', '?', ')', ']', '}', '"', '\'', '…', '»',
+    ])
+}
+
+pub fn parse(raw: &str, platforms: &Platforms) -> Option<Link> {
+    let url = Url::parse(raw).ok()?;
+    let host = normalize_host(url.host_str()?);
+    platforms.for_host(&host)?.identify(&host, &url)
+}
+
+// Follow a short link's redirects (without downloading anything) until they
+// land on a link we can identify
+pub async fn resolve(short_url: &str, platforms: &Platforms) -> Result<CanonicalVideo> {
+    let mut current = short_url.to_string();
+    for _ in 0..MAX_REDIRECTS {
+        let output = Command::new("curl")
+            .args(["-s", "-o", "/dev/null", "--max-time", RESOLVE_TIMEOUT_SECS])
+            .args(["-A", "Mozilla/5.0", "-w", "%{redirect_url}"])
+            .arg(&current)
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to execute curl")?;
+        if !output.status.success() {
+            bail!("curl failed on {} ({})", current, output.status);
+        }
+
+        let next = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if next.is_empty() {
+            bail!("{} does not redirect anywhere", current);
+        }
+        println!("[DEBUG] {} redirects to {}", current, next);
+        if let Some(Link::Video(video)) = parse(&next, platforms) {
+            return Ok(video);
+        }
+        current = next;
+    }
+    bail!("Too many redirects resolving {}", short_url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn find_all(text: &str) -> Vec<Link> {
+        find(text, &Platforms::all())
+    }
+
+    fn parse_all(raw: &str) -> Option<Link> {
+        parse(raw, &Platforms::all())
+    }
+
+    fn video(link: Option<Link>) -> CanonicalVideo {
+        match link {
+            Some(Link::Video(video)) => video,
+            other => panic!("expected a video, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn strips_tracking_params_and_punctuation() {
+        let links = find_all(
+            "lol (https://www.tiktok.com/@some.user/video/7301234567890123456?is_from_webapp=1&sender_device=pc).",
+        );
+        assert_eq!(links.len(), 1);
+        assert_eq!(
+            video(links.into_iter().next()),
+            CanonicalVideo {
+                platform: "tiktok".to_string(),
+                id: "7301234567890123456".to_string(),
+                url: "https://www.tiktok.com/@some.user/video/7301234567890123456".to_string(),
+            }
+        );
+
+        let reel = video(parse_all(
+            "https://instagram.com/reels/C1abc_-9/?igsh=MWd2cnE1",
+        ));
+        assert_eq!(reel.id, "C1abc_-9");
+        assert_eq!(reel.url, "https://www.instagram.com/reel/C1abc_-9/");
+
+        // Same video, different spellings
+        let a = video(parse_all("https://m.tiktok.com/v/7301234567890123456.html"));
+        let b = video(parse_all(
+            "https://www.tiktok.com/@someone/video/7301234567890123456",
+        ));
+        assert_eq!((a.platform, a.id), (b.platform, b.id));
+        let a = video(parse_all("https://www.instagram.com/someone/reel/C1abc/"));
+        assert_eq!(a.id, "C1abc");
+    }
+
+    #[test]
+    fn finds_every_video_once() {
+        let links = find_all(
+            "https://www.tiktok.com/@a/video/1 and https://www.instagram.com/reel/C1abc/ \
+             and again https://m.tiktok.com/v/1.html?lang=en https://vm.tiktok.com/ZMabc/",
+        );
+        let platforms: Vec<&str> = links.iter().map(Link::platform).collect();
+        assert_eq!(platforms, ["tiktok", "instagram", "tiktok"]);
+        assert!(matches!(links[2], Link::Short { .. }));
+    }
+
+    #[test]
+    fn recognizes_short_links_and_ignores_the_rest() {
+        for url in [
+            "https://vm.tiktok.com/ZMabc123/",
+            "https://vt.tiktok.com/ZSabc123/",
+            "https://www.tiktok.com/t/ZTabc123/",
+            "https://www.instagram.com/share/reel/BAabc123",
+        ] {
+            assert!(
+                matches!(parse_all(url), Some(Link::Short { .. })),
+                "{}",
+                url
+            );
+        }
+        for url in [
+            "https://example.com/video/123",
+            "https://www.tiktok.com/@someone",
+            "https://www.instagram.com/someone/",
+            "not a url",
+        ] {
+            assert_eq!(parse_all(url), None, "{}", url);
+        }
+    }
+
+    // Answers every request with a redirect taken from `routes`
+    async fn redirecting_server(routes: Vec<(&'static str, String)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+                let response = match routes.iter().find(|(p, _)| *p == path) {
+                    Some((_, location)) => format!(
+                        "HTTP/1.1 301 Moved Permanently\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        location
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_string()
+                    }
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn resolves_short_links_through_redirects() {
+        let base = redirecting_server(vec![
+            ("/ZMabc123/", "/hop".to_string()),
+            (
+                "/hop",
+                "https://www.tiktok.com/@someone/video/7301234567890123456?_r=1&_t=ZN-abc"
+                    .to_string(),
+            ),
+        ])
+        .await;
+
+        let video = resolve(&format!("{}/ZMabc123/", base), &Platforms::all())
+            .await
+            .unwrap();
+        assert_eq!(video.id, "7301234567890123456");
+        assert_eq!(
+            video.url,
+            "https://www.tiktok.com/@someone/video/7301234567890123456"
+        );
+
+        assert!(
+            resolve(&format!("{}/dead-end", base), &Platforms::all())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use crate::envelope::Envelope;
+use crate::rpc::{Quote, ReplyTarget, RpcError};
+use std::future::Future;
+use std::path::PathBuf;
+
+// Where messages come from and replies go to. The real backend is signal-cli
+// over JSON-RPC (`signal::SignalCli`); tests use the scripted `fake` one.
+pub trait Messenger: Send + Sync + 'static {
+    // Next incoming envelope, or None once the source is gone for good
+    fn receive(&self) -> impl Future<Output = Option<Envelope>> + Send;
+
+    // Send a text message, optionally as a quote-reply and with files
+    // attached. Returns the sent timestamp.
+    fn send(
+        &self,
+        target: &ReplyTarget,
+        message: &str,
+        quote: Option<&Quote>,
+        attachments: &[PathBuf],
+    ) -> impl Future<Output = Result<u64, RpcError>> + Send;
+
+    fn send_reaction(
+        &self,
+        target: &ReplyTarget,
+        message: &Quote,
+        emoji: &str,
+    ) -> impl Future<Output = Result<(), RpcError>> + Send;
+
+    fn send_typing(
+        &self,
+        target: &ReplyTarget,
+        stop: bool,
+    ) -> impl Future<Output = Result<(), RpcError>> + Send;
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    // Everything the bot sent, in order
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Sent {
+        Message {
+            target: ReplyTarget,
+            message: String,
+            quote: Option<Quote>,
+            attachments: Vec<PathBuf>,
+        },
+        Reaction {
+            target: ReplyTarget,
+            timestamp: u64,
+            emoji: String,
+        },
+        Typing {
+            target: ReplyTarget,
+            stop: bool,
+        },
+    }
+
+    // Plays back a fixed list of envelopes (as signal-cli would print them)
+    // and records every outgoing call.
+    #[derive(Default)]
+    pub struct FakeMessenger {
+        script: Mutex<VecDeque<Envelope>>,
+        sent: Mutex<Vec<Sent>>,
+    }
+
+    impl FakeMessenger {
+        pub fn with_envelopes(envelopes: &[&str]) -> Self {
+            let script = envelopes
+                .iter()
+                .map(|json| serde_json::from_str(json).expect("invalid test envelope"))
+                .collect();
+            Self {
+                script: Mutex::new(script),
+                sent: Mutex::default(),
+            }
+        }
+
+        pub fn sent(&self) -> Vec<Sent> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        // Just the text messages, without reactions and typing noise
+        pub fn messages(&self) -> Vec<(ReplyTarget, String, Option<Quote>)> {
+            self.sent()
+                .into_iter()
+                .filter_map(|s| match s {
+                    Sent::Message {
+                        target,
+                        message,
+                        quote,
+                        ..
+                    } => Some((target, message, quote)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        // Files sent along with each text message
+        pub fn attachments(&self) -> Vec<Vec<PathBuf>> {
+            self.sent()
+                .into_iter()
+                .filter_map(|s| match s {
+                    Sent::Message { attachments, .. } => Some(attachments),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        pub fn reactions(&self) -> Vec<String> {
+            self.sent()
+                .into_iter()
+                .filter_map(|s| match s {
+                    Sent::Reaction { emoji, .. } => Some(emoji),
+                    _ => None,
+                })
+                .collect()
+        }
+    }
+
+    impl Messenger for FakeMessenger {
+        async fn receive(&self) -> Option<Envelope> {
+            self.script.lock().unwrap().pop_front()
+        }
+
+        async fn send(
+            &self,
+            target: &ReplyTarget,
+            message: &str,
+            quote: Option<&Quote>,
+            attachments: &[PathBuf],
+        ) -> Result<u64, RpcError> {
+            let mut sent = self.sent.lock().unwrap();
+            sent.push(Sent::Message {
+                target: target.clone(),
+                message: message.to_string(),
+                quote: quote.cloned(),
+                attachments: attachments.to_vec(),
+            });
+            Ok(sent.len() as u64)
+        }
+
+        async fn send_reaction(
+            &self,
+            target: &ReplyTarget,
+            message: &Quote,
+            emoji: &str,
+        ) -> Result<(), RpcError> {
+            self.sent.lock().unwrap().push(Sent::Reaction {
+                target: target.clone(),
+                timestamp: message.timestamp,
+                emoji: emoji.to_string(),
+            });
+            Ok(())
+        }
+
+        async fn send_typing(&self, target: &ReplyTarget, stop: bool) -> Result<(), RpcError> {
+            self.sent.lock().unwrap().push(Sent::Typing {
+                target: target.clone(),
+                stop,
+            });
+            Ok(())
+        }
+    }
+}
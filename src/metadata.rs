@@ -0,0 +1,239 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+// Hashtags in a caption, in any script: #fyp, #köln, #猫
+static HASHTAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"#[\p{L}\p{N}_]+").unwrap());
+
+// What the uploader said about a video, taken from yt-dlp's info JSON and
+// written to metadata.json in the work directory for the summarizer to read.
+// Fields yt-dlp doesn't know for a platform are left out of the file.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoMetadata {
+    // yt-dlp's extractor, e.g. "TikTok" or "Instagram"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    // The creator's own text under the video, hashtags included
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    // Without the '#', from the caption and the video's tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hashtags: Vec<String>,
+    // Display name, and the @handle when the platform has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uploader: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handle: Option<String>,
+    // YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_date: Option<String>,
+    // Seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+    // The sound or song playing, "Title - Artist"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sound: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub views: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub likes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comments: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shares: Option<u64>,
+}
+
+// The fields of yt-dlp's info JSON we map onto VideoMetadata. Extractors
+// disagree on names, so several are tried for the same thing.
+#[derive(Debug, Default, Deserialize)]
+struct Info {
+    extractor_key: Option<String>,
+    webpage_url: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    uploader: Option<String>,
+    channel: Option<String>,
+    creator: Option<String>,
+    uploader_id: Option<String>,
+    upload_date: Option<String>,
+    duration: Option<f64>,
+    track: Option<String>,
+    artist: Option<String>,
+    #[serde(default)]
+    artists: Vec<String>,
+    view_count: Option<u64>,
+    like_count: Option<u64>,
+    comment_count: Option<u64>,
+    repost_count: Option<u64>,
+}
+
+impl VideoMetadata {
+    pub const FILE_NAME: &str = "metadata.json";
+
+    pub fn from_info_json(json: &[u8]) -> Result<Self> {
+        let info: Info = serde_json::from_slice(json).context("Unreadable yt-dlp info JSON")?;
+        Ok(Self::from_info(info))
+    }
+
+    fn from_info(info: Info) -> Self {
+        let caption = info.description.filter(|c| !c.trim().is_empty());
+
+        let mut hashtags: Vec<String> = Vec::new();
+        let from_caption = caption
+            .iter()
+            .flat_map(|c| HASHTAG_REGEX.find_iter(c))
+            .map(|m| m.as_str().trim_start_matches('#'));
+        for tag in from_caption.chain(info.tags.iter().map(|t| t.trim_start_matches('#'))) {
+            if !tag.is_empty() && !hashtags.iter().any(|h| h.eq_ignore_ascii_case(tag)) {
+                hashtags.push(tag.to_string());
+            }
+        }
+
+        let artist = info
+            .artist
+            .or_else(|| (!info.artists.is_empty()).then(|| info.artists.join(", ")));
+        let sound = match (info.track, artist) {
+            // "original sound - someone" already names whose sound it is
+            (Some(track), Some(artist))
+                if !track.contains(&artist) && !track.starts_with("original sound") =>
+            {
+                Some(format!("{} - {}", track, artist))
+            }
+            (Some(track), _) => Some(track),
+            (None, artist) => artist,
+        };
+
+        // Instagram and friends put the display name in `channel` or `creator`
+        let uploader = info.uploader.or(info.channel).or(info.creator);
+        let handle = info
+            .uploader_id
+            .filter(|id| uploader.as_ref() != Some(id) && !id.chars().all(|c| c.is_ascii_digit()))
+            .map(|id| format!("@{}", id.trim_start_matches('@')));
+
+        // yt-dlp falls back to a generic title like "Video by someone" when
+        // the platform has none; the caption says more in that case
+        let title = info
+            .title
+            .filter(|t| caption.as_deref() != Some(t.as_str()));
+
+        Self {
+            platform: info.extractor_key,
+            url: info.webpage_url,
+            title,
+            caption,
+            hashtags,
+            uploader,
+            handle,
+            upload_date: info.upload_date.as_deref().and_then(iso_date),
+            duration: info.duration,
+            sound,
+            views: info.view_count,
+            likes: info.like_count,
+            comments: info.comment_count,
+            shares: info.repost_count,
+        }
+    }
+
+    // metadata.json, pretty-printed so it reads well to the model and to us
+    pub fn write(&self, work_dir: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(work_dir.join(Self::FILE_NAME), json)
+            .with_context(|| format!("Failed to write {}", Self::FILE_NAME))
+    }
+}
+
+// yt-dlp's 20240131 -> 2024-01-31
+fn iso_date(date: &str) -> Option<String> {
+    if date.len() != 8 || !date.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(format!("{}-{}-{}", &date[..4], &date[4..6], &date[6..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Trimmed from real `yt-dlp --dump-json` output
+    const TIKTOK: &str = r##"{
+        "id": "7301234567890123456",
+        "title": "when the bass drops at 3am #fyp #music #Fyp",
+        "description": "when the bass drops at 3am #fyp #music #Fyp",
+        "uploader": "djnightowl",
+        "uploader_id": "6812345678901234567",
+        "channel": "DJ Night Owl",
+        "upload_date": "20240131",
+        "duration": 21,
+        "track": "original sound - djnightowl",
+        "artists": ["DJ Night Owl"],
+        "view_count": 1520000,
+        "like_count": 230000,
+        "comment_count": 1800,
+        "repost_count": 5400,
+        "tags": [],
+        "extractor_key": "TikTok",
+        "webpage_url": "https://www.tiktok.com/@djnightowl/video/7301234567890123456"
+    }"##;
+
+    const INSTAGRAM: &str = r##"{
+        "id": "C2AbCdEfGhI",
+        "title": "Video by cats.daily",
+        "description": "He does this every morning 😭\n.\n.\n#cats #catsofinstagram",
+        "channel": "cats.daily",
+        "uploader_id": "cats.daily",
+        "duration": 12.5,
+        "like_count": 8400,
+        "comment_count": 96,
+        "extractor_key": "Instagram"
+    }"##;
+
+    #[test]
+    fn reads_tiktok_metadata() {
+        let meta = VideoMetadata::from_info_json(TIKTOK.as_bytes()).unwrap();
+        assert_eq!(meta.platform.as_deref(), Some("TikTok"));
+        assert_eq!(meta.title, None);
+        assert_eq!(meta.hashtags, ["fyp", "music"]);
+        assert_eq!(meta.uploader.as_deref(), Some("djnightowl"));
+        // Numeric ids are no use to anyone
+        assert_eq!(meta.handle, None);
+        assert_eq!(meta.upload_date.as_deref(), Some("2024-01-31"));
+        assert_eq!(meta.sound.as_deref(), Some("original sound - djnightowl"));
+        assert_eq!(meta.views, Some(1_520_000));
+        assert_eq!(meta.shares, Some(5400));
+    }
+
+    #[test]
+    fn reads_instagram_metadata() {
+        let meta = VideoMetadata::from_info_json(INSTAGRAM.as_bytes()).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Video by cats.daily"));
+        assert_eq!(meta.hashtags, ["cats", "catsofinstagram"]);
+        assert_eq!(meta.uploader.as_deref(), Some("cats.daily"));
+        assert_eq!(meta.handle, None);
+        assert_eq!(meta.sound, None);
+        assert_eq!(meta.views, None);
+    }
+
+    #[test]
+    fn writes_only_known_fields() {
+        let dir = std::env::temp_dir().join(format!("brainrot-meta-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let meta = VideoMetadata::from_info_json(INSTAGRAM.as_bytes()).unwrap();
+        meta.write(&dir).unwrap();
+
+        let written = fs::read_to_string(dir.join(VideoMetadata::FILE_NAME)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(written.contains("\"likes\": 8400"));
+        assert!(!written.contains("views"));
+        let back: VideoMetadata = serde_json::from_str(&written).unwrap();
+        assert_eq!(back, meta);
+    }
+}
@@ -0,0 +1,587 @@
+use crate::config::Config;
+use crate::error::AnalysisError;
+use crate::frames;
+use crate::language;
+use crate::platform;
+use crate::probe;
+use crate::progress::{Progress, Stage};
+use crate::queue::StageLimits;
+use crate::retry::retry;
+use crate::sheets;
+use crate::transcript;
+use crate::workspace::Workspace;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::process::Command;
+
+// A finished analysis: the summary, plus contact sheets to send along with
+// it, parked in `Config::previews_dir` until the bot claims them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis {
+    pub summary: String,
+    pub previews: Vec<PathBuf>,
+    // What the video is spoken in, from its subtitles or whisper
+    pub language: Option<String>,
+}
+
+// whisper prints this when it works out the language on its own
+static WHISPER_LANGUAGE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"Detected language: ([A-Za-z ]+)").unwrap());
+
+// What a job analyzes: a link for yt-dlp, or a video file we already have
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoSource {
+    Url(String),
+    File(PathBuf),
+}
+
+// The whole analysis, cut off at the job deadline. Giving up drops the
+// running stage, which kills its child process and cleans up the workspace.
+// `reply_language` is what the summary should be written in, if not the default.
+pub async fn analyze_video(
+    source: &VideoSource,
+    reply_language: Option<&str>,
+    progress: &Progress,
+    config: &Config,
+    limits: &StageLimits,
+) -> Result<Analysis, AnalysisError> {
+    let deadline = config.timeouts.job;
+    let analysis = analyze(source, reply_language, progress, config, limits);
+    match tokio::time::timeout(deadline, analysis).await {
+        Ok(result) => result,
+        Err(_) => Err(AnalysisError::Timeout(format!(
+            "job took longer than {}s",
+            deadline.as_secs()
+        ))),
+    }
+}
+
+async fn analyze(
+    source: &VideoSource,
+    reply_language: Option<&str>,
+    progress: &Progress,
+    config: &Config,
+    limits: &StageLimits,
+) -> Result<Analysis, AnalysisError> {
+    let timeouts = &config.timeouts;
+    // Private directory for this job, removed (or kept) when it goes out of scope
+    let mut workspace = Workspace::create(&config.work_root, config.retention)?;
+    let temp_dir = workspace.path().to_path_buf();
+    println!("[DEBUG] Working in {}", temp_dir.display());
+
+    let subs_dir = temp_dir.join("subs");
+    fs::create_dir_all(&subs_dir).context("Failed to create subs dir")?;
+
+    let video_path = match source {
+        VideoSource::Url(url) => {
+            progress.stage(Stage::Downloading);
+            // Look before we leap: live streams and hour-long videos are
+            // turned down without downloading a single frame
+            let plan = retry(&config.download_retry, "yt-dlp probe", || async {
+                let _slot = limits.acquire(Stage::Downloading).await;
+                probe::probe(url, &temp_dir, config).await
+            })
+            .await?;
+            plan.metadata.write(&temp_dir)?;
+            let sub_langs = language::ytdlp_sub_langs(&config.sub_languages);
+            retry(&config.download_retry, "yt-dlp", || async {
+                let _slot = limits.acquire(Stage::Downloading).await;
+                download_video_and_subs(
+                    url,
+                    plan.format.as_deref(),
+                    &sub_langs,
+                    &temp_dir,
+                    &subs_dir,
+                    timeouts.download,
+                )
+                .await
+            })
+            .await?
+        }
+        VideoSource::File(path) => copy_attachment(path, &temp_dir)?,
+    };
+
+    let mut spoken = None;
+    let has_subs = fs::read_dir(&subs_dir)?.next().is_some();
+    if !has_subs {
+        println!("[DEBUG] No subtitles available.");
+        progress.stage(Stage::Transcribing);
+        let _slot = limits.acquire(Stage::Transcribing).await;
+        spoken = transcribe_audio(&video_path, &subs_dir, timeouts.whisper).await?;
+    }
+
+    // Raw captions are full of scrolling repeats and markup; the model gets
+    // a clean transcript instead
+    match subtitle_file(&subs_dir, &config.sub_languages)? {
+        Some((subtitles, sub_language)) => {
+            // Subtitle files are named after their language, whisper's aren't
+            spoken = sub_language.or(spoken);
+            match transcript::write(&temp_dir, &subtitles, spoken.as_deref()) {
+                Ok(cues) => println!(
+                    "📝 Transcript of {} lines from {}",
+                    cues,
+                    subtitles.display()
+                ),
+                Err(e) => eprintln!("Warning: No transcript: {:#}", e),
+            }
+        }
+        None => println!("[DEBUG] No transcript, the video goes without words."),
+    }
+    if let Some(ref spoken) = spoken {
+        println!("🗣️ Spoken language: {}", language::name(spoken));
+    }
+
+    progress.stage(Stage::ExtractingFrames);
+    let contact_sheets = {
+        let _slot = limits.acquire(Stage::ExtractingFrames).await;
+        let extracted =
+            frames::extract(&temp_dir, &video_path, &config.frames, timeouts.ffmpeg).await?;
+        let frames = frames::dedup(
+            &temp_dir,
+            extracted,
+            config.dedup_threshold,
+            timeouts.ffmpeg,
+        )
+        .await?;
+        if config.sheets.enabled {
+            // Sheets are a saving, not a requirement: without them opencode
+            // just reads the frames one by one
+            sheets::build(&temp_dir, &frames, &config.sheets, timeouts.ffmpeg)
+                .await
+                .unwrap_or_else(|e| {
+                    eprintln!("Warning: No contact sheets, using single frames: {}", e);
+                    Vec::new()
+                })
+        } else {
+            Vec::new()
+        }
+    };
+
+    progress.stage(Stage::Summarizing);
+    let summary = retry(&config.opencode_retry, "opencode", || async {
+        let _slot = limits.acquire(Stage::Summarizing).await;
+        let languages = Languages {
+            spoken: spoken.as_deref(),
+            reply: reply_language,
+        };
+        summarize(
+            &temp_dir,
+            !contact_sheets.is_empty(),
+            languages,
+            timeouts.opencode,
+        )
+        .await
+    })
+    .await?;
+
+    let previews = if config.sheets.attach {
+        park_previews(&contact_sheets, &temp_dir, &config.previews_dir())?
+    } else {
+        Vec::new()
+    };
+
+    workspace.succeeded();
+    Ok(Analysis {
+        summary,
+        previews,
+        language: spoken,
+    })
+}
+
+// Move contact sheets out of the workspace before it is cleaned up
+fn park_previews(sheets: &[PathBuf], work_dir: &Path, dir: &Path) -> Result<Vec<PathBuf>> {
+    if sheets.is_empty() {
+        return Ok(Vec::new());
+    }
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let job = work_dir.file_name().unwrap_or_default().to_string_lossy();
+    let mut parked = Vec::new();
+    for (n, sheet) in sheets.iter().enumerate() {
+        let dest = dir.join(format!("pending-{}-{}.jpg", job, n + 1));
+        // Copy, since the workspace may be kept for debugging
+        fs::copy(sheet, &dest)
+            .with_context(|| format!("Failed to park preview {}", sheet.display()))?;
+        parked.push(dest);
+    }
+    Ok(parked)
+}
+
+// Run a tool to completion, capturing its output, and kill it once it takes
+// longer than `limit` or the job is dropped. The tool gets a process group of
+// its own so whatever it starts (yt-dlp's ffmpeg, opencode's helpers) goes
+// down with it. The inner result is the tool failing to start, for the
+// caller to classify.
+pub async fn output_within(
+    command: &mut Command,
+    tool: &str,
+    limit: Duration,
+) -> Result<std::io::Result<Output>, AnalysisError> {
+    command
+        .process_group(0)
+        .kill_on_drop(true)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return Ok(Err(e)),
+    };
+    let group = ProcessGroup(child.id());
+
+    match tokio::time::timeout(limit, child.wait_with_output()).await {
+        Ok(output) => {
+            group.release();
+            Ok(output)
+        }
+        Err(_) => Err(AnalysisError::Timeout(format!(
+            "{} took longer than {}s",
+            tool,
+            limit.as_secs()
+        ))),
+    }
+}
+
+// Kills a tool's process group when dropped, unless it finished on its own
+struct ProcessGroup(Option<u32>);
+
+impl ProcessGroup {
+    fn release(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(pgid) = self.0 {
+            // SAFETY: kill(2) takes plain integers; a group that is already
+            // gone just makes it fail with ESRCH
+            unsafe {
+                libc::kill(-(pgid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+// What the video is in, and what the summary should be in
+#[derive(Debug, Clone, Copy)]
+struct Languages<'a> {
+    spoken: Option<&'a str>,
+    reply: Option<&'a str>,
+}
+
+async fn summarize(
+    work_dir: &Path,
+    contact_sheets: bool,
+    languages: Languages<'_>,
+    limit: Duration,
+) -> Result<String, AnalysisError> {
+    let visuals = if contact_sheets {
+        "- 'sheets/' directory containing contact sheets (sheet_01.jpg, etc), each a grid of \
+        frames read left to right, top to bottom, labeled with when in the video they were \
+        taken. Look at these, not at 'frames/'"
+    } else {
+        "- 'frames/' directory containing extracted frames (frame_001.jpg, etc), \
+        with 'frames/index.json' giving the second each frame was taken at"
+    };
+    let mut prompt = format!(
+        "You are a video analyzer. \
+        The current directory contains a video processed into: \
+        {} \
+        - 'transcript.txt' with what is said, one line per caption starting with \
+        the time it was said (if available) \
+        - 'metadata.json' with the uploader's caption, hashtags, sound, upload date \
+        and view/like counts (if available) \
+        \
+        Analyze the content based on these files. \
+        1. Summarize what happens. Include Text and captions for important context. \
+        Cite the caption and the sound when they add to it, and mention when it is trending \
+        2. Summarize the sentiment/opinions expressed. \
+        3. Rate the 'Brainrot Level' (1-10). \
+        Natural formatting, no '*', keep Headings. START output from 'Summary' \
+        Keep your response CONCISE \
+        No more than 3 sentances of Summary \
+        No more than 2 sentances of sentiment and opinions \
+        No more than 1 sentance of brainrot level",
+        visuals
+    );
+    if let Some(spoken) = languages.spoken {
+        prompt.push_str(&format!(" The video is in {}.", language::name(spoken)));
+    }
+    if let Some(reply) = languages.reply {
+        prompt.push_str(&format!(
+            " Write your whole answer in {}, whatever language the video is in, \
+            keeping the headings' meaning.",
+            language::name(reply)
+        ));
+    }
+
+    let mut command = Command::new("opencode");
+    command
+        .current_dir(work_dir)
+        .args(["-m", "opencode/gemini-3-flash", "run", &prompt]);
+    let output = output_within(&mut command, "opencode", limit)
+        .await?
+        .map_err(|e| AnalysisError::LlmFailed(format!("Failed to run opencode: {}", e)))?;
+
+    if !output.status.success() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        return Err(AnalysisError::LlmFailed(err.trim().to_string()));
+    }
+
+    let raw = String::from_utf8_lossy(&output.stdout);
+    let trimmed = raw.trim();
+    if trimmed.is_empty() {
+        return Err(AnalysisError::LlmFailed(
+            "opencode returned an empty summary".to_string(),
+        ));
+    }
+    if trimmed.len() > 3000 {
+        Ok(format!(
+            "{}...\n\n(truncated)",
+            &trimmed[..trimmed.floor_char_boundary(3000)]
+        ))
+    } else {
+        Ok(trimmed.to_string())
+    }
+}
+
+async fn download_video_and_subs(
+    url: &str,
+    format: Option<&str>,
+    sub_langs: &str,
+    work_dir: &Path,
+    subs_dir: &Path,
+    limit: Duration,
+) -> Result<PathBuf, AnalysisError> {
+    let mut command = Command::new("yt-dlp");
+    command
+        .current_dir(work_dir)
+        .args([
+            "-o",
+            "video.%(ext)s", // Explicitly name it video.ext
+            "--write-subs",
+            "--write-auto-subs",
+            "--sub-langs",
+            sub_langs,
+            "--sub-format",
+            "vtt",
+            // A link can carry a playlist; we only ever want the one video the
+            // probe looked at
+            "--no-playlist",
+        ])
+        .args(platform::ytdlp_args(url));
+    if let Some(format) = format {
+        command.args(["-f", format]);
+    }
+    command
+        .arg(url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = output_within(&mut command, "yt-dlp", limit)
+        .await?
+        .context("Failed to run yt-dlp")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AnalysisError::from_ytdlp(&stderr));
+    }
+
+    let mut video_path = None;
+
+    // Move any .vtt files to the subs directory and find the video file
+    let read_dir = fs::read_dir(work_dir)?;
+    for entry in read_dir.flatten() {
+        let path = entry.path();
+        if let Some(ext) = path.extension() {
+            if ext == "vtt" || ext == "srt" {
+                let file_name = path.file_name().unwrap();
+                let dest = subs_dir.join(file_name);
+                fs::rename(path, dest)?;
+            } else if let Some(stem) = path.file_stem() {
+                // If the file is named "video" and it's not a subtitle file, assume it's the video
+                if stem == "video" {
+                    video_path = Some(path);
+                }
+            }
+        }
+    }
+
+    let video_path = video_path.ok_or_else(|| {
+        AnalysisError::NoVideo("Could not find downloaded video file".to_string())
+    })?;
+
+    Ok(video_path)
+}
+
+// The subtitles to build the transcript from, in the most preferred
+// language there is, and that language. yt-dlp names them
+// video.<language>.vtt; whisper's video.vtt has none.
+fn subtitle_file(
+    subs_dir: &Path,
+    preferred: &[String],
+) -> Result<Option<(PathBuf, Option<String>)>> {
+    let mut files: Vec<(PathBuf, Option<String>)> = fs::read_dir(subs_dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "vtt" || ext == "srt")
+        })
+        .map(|path| {
+            let language = Path::new(path.file_stem().unwrap_or_default())
+                .extension()
+                .map(|lang| language::normalize(&lang.to_string_lossy()));
+            (path, language)
+        })
+        .collect();
+    files.sort();
+    let rank = |language: &Option<String>| {
+        language
+            .as_ref()
+            .and_then(|l| preferred.iter().position(|p| p == l))
+            .unwrap_or(preferred.len())
+    };
+    Ok(files.into_iter().min_by_key(|(_, language)| rank(language)))
+}
+
+// Put a forwarded attachment where the rest of the pipeline expects the download
+fn copy_attachment(path: &Path, work_dir: &Path) -> Result<PathBuf> {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+    let video_path = work_dir.join(format!("video.{}", ext));
+    fs::copy(path, &video_path)
+        .with_context(|| format!("Failed to copy attachment {}", path.display()))?;
+    Ok(video_path)
+}
+
+// Whisper works out the language by itself; returns what it detected
+async fn transcribe_audio(
+    video_path: &Path,
+    subs_dir: &Path,
+    limit: Duration,
+) -> Result<Option<String>, AnalysisError> {
+    let mut command = Command::new("whisper");
+    command
+        .arg(video_path.to_str().unwrap())
+        .arg("--model")
+        .arg("tiny")
+        .arg("--output_format")
+        .arg("vtt")
+        .arg("--output_dir")
+        .arg(subs_dir.to_str().unwrap())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    // Like a failed run, a stuck one only costs us the transcript; the frames
+    // still make for a summary. Not being able to start whisper at all is a
+    // setup problem worth failing over.
+    let output = match output_within(&mut command, "whisper", limit).await {
+        Ok(output) => output.map_err(|e| {
+            AnalysisError::TranscriptionFailed(format!("Failed to run whisper: {}", e))
+        })?,
+        Err(e) => {
+            eprintln!("Warning: {}, going on without a transcript", e);
+            return Ok(None);
+        }
+    };
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        eprintln!("Warning: Whisper failed: {}", stderr);
+        // We proceed without subtitles rather than failing the whole analysis
+        return Ok(None);
+    }
+    println!("[DEBUG] Whisper generated subtitles successfully.");
+
+    Ok(detected_language(&String::from_utf8_lossy(&output.stdout)))
+}
+
+fn detected_language(whisper_output: &str) -> Option<String> {
+    let name = WHISPER_LANGUAGE_REGEX.captures(whisper_output)?[1]
+        .trim()
+        .to_string();
+    Some(language::normalize(&name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn picks_subtitles_in_the_preferred_language() {
+        let dir = std::env::temp_dir().join(format!("brainrot-subs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["video.en.vtt", "video.dan-DK.vtt", "video.es.vtt"] {
+            fs::write(dir.join(name), "WEBVTT").unwrap();
+        }
+        let preferred =
+            |langs: &[&str]| -> Vec<String> { langs.iter().map(|l| l.to_string()).collect() };
+
+        let (path, language) = subtitle_file(&dir, &preferred(&["da", "en"]))
+            .unwrap()
+            .unwrap();
+        assert!(path.ends_with("video.dan-DK.vtt"));
+        assert_eq!(language.as_deref(), Some("da"));
+
+        let (_, language) = subtitle_file(&dir, &preferred(&["fr", "es"]))
+            .unwrap()
+            .unwrap();
+        assert_eq!(language.as_deref(), Some("es"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reads_the_language_whisper_detected() {
+        let output = "Detecting language using up to the first 30 seconds. \
+            Use `--language` to specify the language\n\
+            Detected language: Danish\n\
+            [00:00.000 --> 00:02.400]  Nej, hvad sker der?\n";
+        assert_eq!(detected_language(output).as_deref(), Some("da"));
+        assert_eq!(detected_language("[00:00.000 --> 00:02.400]  hi"), None);
+    }
+
+    #[tokio::test]
+    async fn kills_tools_that_run_too_long() {
+        let started = Instant::now();
+        let mut command = Command::new("sleep");
+        command.arg("30");
+        let result = output_within(&mut command, "sleep", Duration::from_millis(100)).await;
+
+        assert!(matches!(result, Err(AnalysisError::Timeout(_))));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let mut command = Command::new("true");
+        let output = output_within(&mut command, "true", Duration::from_secs(5)).await;
+        assert!(output.unwrap().unwrap().status.success());
+    }
+
+    // Running, as opposed to gone or a zombie waiting to be reaped
+    fn alive(pid: &str) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", pid))
+            .is_ok_and(|stat| !stat.split(") ").nth(1).unwrap_or("").starts_with('Z'))
+    }
+
+    #[tokio::test]
+    async fn kills_what_the_tool_started_too() {
+        let pid_file = std::env::temp_dir().join(format!("brainrot-pgid-{}", std::process::id()));
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("sleep 30 & echo $! > {}; wait", pid_file.display()));
+        let result = output_within(&mut command, "sh", Duration::from_millis(300)).await;
+        assert!(matches!(result, Err(AnalysisError::Timeout(_))));
+
+        let grandchild = fs::read_to_string(&pid_file).unwrap();
+        fs::remove_file(&pid_file).unwrap();
+        let grandchild = grandchild.trim();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while alive(grandchild) && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive(grandchild));
+    }
+}
@@ -0,0 +1,530 @@
+use crate::link::{CanonicalVideo, Link};
+use anyhow::{Result, bail};
+use url::Url;
+
+// Everything the bot knows about one video site. Adding a site means adding
+// an implementation here and listing it in `builtin`.
+pub trait Platform: Send + Sync {
+    // Lowercase id used in config, the job store and cache keys
+    fn name(&self) -> &'static str;
+    // For logs and replies, e.g. "🔗 TikTok detected"
+    fn display_name(&self) -> &'static str;
+    fn emoji(&self) -> &'static str;
+    // Does a link on `host` (lowercase, without "www.") belong to us?
+    fn matches(&self, host: &str) -> bool;
+    // The video behind a link on one of our hosts: a canonical id and URL, a
+    // short link that has to be followed first, or None for non-video pages
+    fn identify(&self, host: &str, url: &Url) -> Option<Link>;
+    // Extra yt-dlp arguments for this site
+    fn ytdlp_args(&self) -> &'static [&'static str] {
+        &[]
+    }
+}
+
+// Sites whose default download is a full-HD (or bigger) stream; 720p is
+// plenty for frames and keeps downloads quick
+const PREFER_720P: &[&str] = &["-S", "res:720"];
+
+// Every platform we support, in the order they are tried
+fn builtin() -> Vec<Box<dyn Platform>> {
+    vec![
+        Box::new(TikTok),
+        Box::new(Instagram),
+        Box::new(YouTubeShorts),
+        Box::new(Twitter),
+        Box::new(Reddit),
+        Box::new(Snapchat),
+        Box::new(Facebook),
+    ]
+}
+
+// The platforms enabled in the config
+pub struct Platforms {
+    enabled: Vec<Box<dyn Platform>>,
+}
+
+impl Platforms {
+    pub fn all() -> Self {
+        Self { enabled: builtin() }
+    }
+
+    // Only the named platforms; an empty list means all of them
+    pub fn select(names: &[String]) -> Result<Self> {
+        if names.is_empty() {
+            return Ok(Self::all());
+        }
+        let mut available = builtin();
+        let mut enabled: Vec<Box<dyn Platform>> = Vec::new();
+        for name in names {
+            match available.iter().position(|p| p.name() == name) {
+                Some(index) => enabled.push(available.remove(index)),
+                // Listed twice
+                None if enabled.iter().any(|p| p.name() == name) => {}
+                None => bail!(
+                    "unknown platform '{}', expected one of: {}",
+                    name,
+                    builtin()
+                        .iter()
+                        .map(|p| p.name())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+            }
+        }
+        Ok(Self { enabled })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Platform> {
+        self.enabled
+            .iter()
+            .find(|p| p.name() == name)
+            .map(|p| p.as_ref())
+    }
+
+    pub fn for_host(&self, host: &str) -> Option<&dyn Platform> {
+        self.enabled
+            .iter()
+            .find(|p| p.matches(host))
+            .map(|p| p.as_ref())
+    }
+}
+
+// yt-dlp arguments for whichever platform `url` belongs to
+pub fn ytdlp_args(url: &str) -> &'static [&'static str] {
+    let Some(host) = Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(normalize_host))
+    else {
+        return &[];
+    };
+    builtin()
+        .into_iter()
+        .find(|p| p.matches(&host))
+        .map(|p| p.ytdlp_args())
+        .unwrap_or_default()
+}
+
+pub fn normalize_host(host: &str) -> String {
+    let host = host.to_ascii_lowercase();
+    match host.strip_prefix("www.") {
+        Some(rest) => rest.to_string(),
+        None => host,
+    }
+}
+
+fn segments(url: &Url) -> Vec<&str> {
+    url.path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+fn query<'a>(url: &'a Url, key: &str) -> Option<std::borrow::Cow<'a, str>> {
+    url.query_pairs().find(|(k, _)| k == key).map(|(_, v)| v)
+}
+
+fn video(platform: &dyn Platform, id: &str, url: String) -> Option<Link> {
+    Some(Link::Video(CanonicalVideo {
+        platform: platform.name().to_string(),
+        id: id.to_string(),
+        url,
+    }))
+}
+
+fn short(platform: &dyn Platform, url: &Url) -> Option<Link> {
+    Some(Link::Short {
+        platform: platform.name().to_string(),
+        url: url.to_string(),
+    })
+}
+
+fn is_digits(id: &str) -> bool {
+    !id.is_empty() && id.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_code(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+pub struct TikTok;
+
+impl Platform for TikTok {
+    fn name(&self) -> &'static str {
+        "tiktok"
+    }
+    fn display_name(&self) -> &'static str {
+        "TikTok"
+    }
+    fn emoji(&self) -> &'static str {
+        "🔗"
+    }
+    fn matches(&self, host: &str) -> bool {
+        host == "tiktok.com" || host.ends_with(".tiktok.com")
+    }
+
+    // /@user/video/<id>, /@user/photo/<id>, /v/<id>.html, /embed/v2/<id>;
+    // vm./vt. hosts and /t/<code> are short links
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        if matches!(host, "vm.tiktok.com" | "vt.tiktok.com" | "t.tiktok.com") {
+            return short(self, url);
+        }
+        let (user, id) = match segments(url)[..] {
+            [user, "video" | "photo", id, ..] if user.starts_with('@') => (user, id),
+            ["v", id] => ("@", id.trim_end_matches(".html")),
+            ["embed", "v2", id] | ["embed", id] => ("@", id),
+            ["t", _] => return short(self, url),
+            _ => return None,
+        };
+        if !is_digits(id) {
+            return None;
+        }
+        video(
+            self,
+            id,
+            format!("https://www.tiktok.com/{}/video/{}", user, id),
+        )
+    }
+}
+
+pub struct Instagram;
+
+impl Platform for Instagram {
+    fn name(&self) -> &'static str {
+        "instagram"
+    }
+    fn display_name(&self) -> &'static str {
+        "Instagram"
+    }
+    fn emoji(&self) -> &'static str {
+        "📸"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(host, "instagram.com" | "m.instagram.com")
+    }
+
+    // /reel/<code>, /reels/<code>, /p/<code>, /tv/<code>, optionally after
+    // /<user>. /share/reel/<code> carries a share code, not the reel's.
+    fn identify(&self, _host: &str, url: &Url) -> Option<Link> {
+        let segments = segments(url);
+        if matches!(segments.first(), Some(&("share" | "t" | "v"))) {
+            return short(self, url);
+        }
+        let at = segments
+            .iter()
+            .position(|s| matches!(*s, "reel" | "reels" | "p" | "tv"))?;
+        if at > 1 {
+            return None;
+        }
+        let code = segments.get(at + 1)?;
+        if !is_code(code) {
+            return None;
+        }
+        let kind = match segments[at] {
+            "p" => "p",
+            "tv" => "tv",
+            _ => "reel",
+        };
+        video(
+            self,
+            code,
+            format!("https://www.instagram.com/{}/{}/", kind, code),
+        )
+    }
+}
+
+pub struct YouTubeShorts;
+
+impl Platform for YouTubeShorts {
+    fn name(&self) -> &'static str {
+        "youtube"
+    }
+    fn display_name(&self) -> &'static str {
+        "YouTube Short"
+    }
+    fn emoji(&self) -> &'static str {
+        "▶️"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(host, "youtube.com" | "m.youtube.com" | "youtu.be")
+    }
+
+    // /shorts/<id> and youtu.be/<id>; regular watch pages are left alone
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        let id = match (host, &segments(url)[..]) {
+            ("youtu.be", [id]) => *id,
+            (_, ["shorts", id]) => *id,
+            _ => return None,
+        };
+        if id.len() != 11 || !is_code(id) {
+            return None;
+        }
+        video(self, id, format!("https://www.youtube.com/shorts/{}", id))
+    }
+
+    fn ytdlp_args(&self) -> &'static [&'static str] {
+        PREFER_720P
+    }
+}
+
+pub struct Twitter;
+
+impl Platform for Twitter {
+    fn name(&self) -> &'static str {
+        "twitter"
+    }
+    fn display_name(&self) -> &'static str {
+        "X/Twitter"
+    }
+    fn emoji(&self) -> &'static str {
+        "🐦"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(
+            host,
+            "x.com" | "twitter.com" | "mobile.twitter.com" | "mobile.x.com" | "t.co"
+        )
+    }
+
+    // /<user>/status/<id>, optionally followed by /video/1
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        if host == "t.co" {
+            return short(self, url);
+        }
+        let [user, "status", id, ..] = segments(url)[..] else {
+            return None;
+        };
+        if !is_digits(id) {
+            return None;
+        }
+        video(self, id, format!("https://x.com/{}/status/{}", user, id))
+    }
+
+    fn ytdlp_args(&self) -> &'static [&'static str] {
+        PREFER_720P
+    }
+}
+
+pub struct Reddit;
+
+impl Platform for Reddit {
+    fn name(&self) -> &'static str {
+        "reddit"
+    }
+    fn display_name(&self) -> &'static str {
+        "Reddit"
+    }
+    fn emoji(&self) -> &'static str {
+        "👽"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(
+            host,
+            "reddit.com" | "old.reddit.com" | "new.reddit.com" | "redd.it" | "v.redd.it"
+        )
+    }
+
+    // /r/<sub>/comments/<id>/..., /comments/<id> and redd.it/<id>; share
+    // links (/r/<sub>/s/<code>) and v.redd.it media links need following
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        if host == "v.redd.it" {
+            return short(self, url);
+        }
+        let id = match (host, &segments(url)[..]) {
+            ("redd.it", [id]) => *id,
+            (_, ["r", _, "comments", id, ..]) | (_, ["comments", id, ..]) => *id,
+            (_, ["r", _, "s", _]) => return short(self, url),
+            _ => return None,
+        };
+        if !id.bytes().all(|b| b.is_ascii_alphanumeric()) || id.is_empty() {
+            return None;
+        }
+        video(self, id, format!("https://www.reddit.com/comments/{}/", id))
+    }
+
+    fn ytdlp_args(&self) -> &'static [&'static str] {
+        PREFER_720P
+    }
+}
+
+pub struct Snapchat;
+
+impl Platform for Snapchat {
+    fn name(&self) -> &'static str {
+        "snapchat"
+    }
+    fn display_name(&self) -> &'static str {
+        "Snapchat Spotlight"
+    }
+    fn emoji(&self) -> &'static str {
+        "👻"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(host, "snapchat.com" | "t.snapchat.com")
+    }
+
+    // /spotlight/<id>; t.snapchat.com/<code> is a short link
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        if host == "t.snapchat.com" {
+            return short(self, url);
+        }
+        let ["spotlight", id] = segments(url)[..] else {
+            return None;
+        };
+        if !is_code(id) {
+            return None;
+        }
+        video(
+            self,
+            id,
+            format!("https://www.snapchat.com/spotlight/{}", id),
+        )
+    }
+}
+
+pub struct Facebook;
+
+impl Platform for Facebook {
+    fn name(&self) -> &'static str {
+        "facebook"
+    }
+    fn display_name(&self) -> &'static str {
+        "Facebook"
+    }
+    fn emoji(&self) -> &'static str {
+        "📘"
+    }
+    fn matches(&self, host: &str) -> bool {
+        matches!(
+            host,
+            "facebook.com" | "m.facebook.com" | "web.facebook.com" | "fb.watch"
+        )
+    }
+
+    // /reel/<id>, /watch?v=<id>, /<page>/videos/<id>; fb.watch/<code> and
+    // /share/r/<code> need following
+    fn identify(&self, host: &str, url: &Url) -> Option<Link> {
+        if host == "fb.watch" {
+            return short(self, url);
+        }
+        let segments = segments(url);
+        let id = match segments[..] {
+            ["reel", id] => id.to_string(),
+            ["watch"] => query(url, "v")?.into_owned(),
+            [_, "videos", id] | [_, "videos", _, id] => id.to_string(),
+            ["share", "r" | "v", _] => return short(self, url),
+            _ => return None,
+        };
+        if !is_digits(&id) {
+            return None;
+        }
+        video(self, &id, format!("https://www.facebook.com/reel/{}", id))
+    }
+
+    fn ytdlp_args(&self) -> &'static [&'static str] {
+        PREFER_720P
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identify(raw: &str) -> Option<Link> {
+        let url = Url::parse(raw).unwrap();
+        let host = normalize_host(url.host_str().unwrap());
+        Platforms::all().for_host(&host)?.identify(&host, &url)
+    }
+
+    fn id(raw: &str) -> (String, String) {
+        match identify(raw) {
+            Some(Link::Video(video)) => (video.platform, video.id),
+            other => panic!("expected a video for {}, got {:?}", raw, other),
+        }
+    }
+
+    fn pair(platform: &str, id: &str) -> (String, String) {
+        (platform.to_string(), id.to_string())
+    }
+
+    #[test]
+    fn identifies_videos_on_every_platform() {
+        assert_eq!(
+            id("https://youtube.com/shorts/dQw4w9WgXcQ?si=abc"),
+            pair("youtube", "dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            id("https://youtu.be/dQw4w9WgXcQ"),
+            pair("youtube", "dQw4w9WgXcQ")
+        );
+        assert_eq!(
+            id("https://x.com/someone/status/1790000000000000000/video/1"),
+            pair("twitter", "1790000000000000000")
+        );
+        assert_eq!(
+            id("https://mobile.twitter.com/someone/status/1790000000000000000?s=20"),
+            pair("twitter", "1790000000000000000")
+        );
+        assert_eq!(
+            id("https://old.reddit.com/r/funny/comments/1abcde/some_title/"),
+            pair("reddit", "1abcde")
+        );
+        assert_eq!(id("https://redd.it/1abcde"), pair("reddit", "1abcde"));
+        assert_eq!(
+            id("https://www.snapchat.com/spotlight/W7_EDlXWTBiXAEEniNoMPwAAYYXBpaHJhcHl4"),
+            pair("snapchat", "W7_EDlXWTBiXAEEniNoMPwAAYYXBpaHJhcHl4")
+        );
+        assert_eq!(
+            id("https://www.facebook.com/reel/1234567890123456?mibextid=abc"),
+            pair("facebook", "1234567890123456")
+        );
+        assert_eq!(
+            id("https://m.facebook.com/watch/?v=1234567890123456"),
+            pair("facebook", "1234567890123456")
+        );
+        assert_eq!(
+            id("https://www.facebook.com/somepage/videos/1234567890123456/"),
+            pair("facebook", "1234567890123456")
+        );
+    }
+
+    #[test]
+    fn short_links_and_non_videos() {
+        for raw in [
+            "https://t.co/AbCdEf123",
+            "https://www.reddit.com/r/funny/s/AbCdEf123",
+            "https://v.redd.it/abc123xyz",
+            "https://t.snapchat.com/AbCdEf12",
+            "https://fb.watch/abcDEF123/",
+            "https://www.facebook.com/share/r/AbCdEf123/",
+        ] {
+            assert!(matches!(identify(raw), Some(Link::Short { .. })), "{}", raw);
+        }
+        for raw in [
+            "https://www.youtube.com/watch?v=dQw4w9WgXcQ",
+            "https://x.com/someone",
+            "https://www.reddit.com/r/funny/",
+            "https://www.facebook.com/somepage",
+        ] {
+            assert_eq!(identify(raw), None, "{}", raw);
+        }
+    }
+
+    #[test]
+    fn selects_platforms_from_config() {
+        let names = ["tiktok".to_string(), "reddit".to_string()];
+        let platforms = Platforms::select(&names).unwrap();
+        assert!(platforms.get("reddit").is_some());
+        assert!(platforms.get("instagram").is_none());
+        assert!(platforms.for_host("instagram.com").is_none());
+
+        assert!(Platforms::select(&["myspace".to_string()]).is_err());
+        assert_eq!(
+            ytdlp_args("https://www.reddit.com/comments/1abcde/"),
+            PREFER_720P
+        );
+        assert!(ytdlp_args("https://www.tiktok.com/@a/video/1").is_empty());
+        assert_eq!(ytdlp_args("https://youtu.be/dQw4w9WgXcQ"), PREFER_720P);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::config::Config;
+use crate::error::AnalysisError;
+use crate::metadata::VideoMetadata;
+use crate::pipeline::output_within;
+use anyhow::Context;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+
+// Frames need to be legible, not pretty: the smallest format whose shorter
+// side reaches this many pixels is good enough
+const MIN_SHORT_SIDE: u32 = 360;
+
+// The parts of yt-dlp's info JSON we look at before downloading
+#[derive(Debug, Default, Deserialize)]
+pub struct VideoInfo {
+    pub duration: Option<f64>,
+    pub filesize: Option<f64>,
+    pub filesize_approx: Option<f64>,
+    pub is_live: Option<bool>,
+    // not_live, is_live, is_upcoming, was_live or post_live
+    pub live_status: Option<String>,
+    // public, unlisted, private, premium_only, subscriber_only or needs_auth
+    pub availability: Option<String>,
+    #[serde(default)]
+    pub formats: Vec<Format>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Format {
+    pub format_id: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub vcodec: Option<String>,
+    pub acodec: Option<String>,
+    pub filesize: Option<f64>,
+    pub filesize_approx: Option<f64>,
+    // Average bitrate in kbit/s, for guessing sizes yt-dlp doesn't know
+    pub tbr: Option<f64>,
+}
+
+impl Format {
+    fn has_video(&self) -> bool {
+        self.vcodec.as_deref().is_some_and(|c| c != "none")
+    }
+
+    fn has_audio(&self) -> bool {
+        self.acodec.as_deref().is_some_and(|c| c != "none")
+    }
+
+    fn short_side(&self) -> Option<u32> {
+        Some(self.width?.min(self.height?))
+    }
+
+    fn size(&self, duration: Option<f64>) -> Option<f64> {
+        self.filesize
+            .or(self.filesize_approx)
+            .or_else(|| Some(self.tbr? * 1000.0 / 8.0 * duration?))
+    }
+}
+
+// What the probe decided
+#[derive(Debug, PartialEq)]
+pub struct Plan {
+    // yt-dlp -f selector, None to let yt-dlp pick
+    pub format: Option<String>,
+    // Caption, uploader and stats for the summarizer
+    pub metadata: VideoMetadata,
+}
+
+// Ask yt-dlp about the video without downloading it, keep its answer as
+// info.json in the workspace, and refuse videos we shouldn't spend an hour on
+pub async fn probe(url: &str, work_dir: &Path, config: &Config) -> Result<Plan, AnalysisError> {
+    let mut command = Command::new("yt-dlp");
+    command
+        .current_dir(work_dir)
+        .args(["--dump-json", "--no-playlist", "--skip-download"])
+        .arg(url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = output_within(&mut command, "yt-dlp probe", config.timeouts.download)
+        .await?
+        .context("Failed to run yt-dlp")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(AnalysisError::from_ytdlp(&stderr));
+    }
+
+    fs::write(work_dir.join("info.json"), &output.stdout).context("Failed to save info.json")?;
+    let info: VideoInfo = serde_json::from_slice(&output.stdout)
+        .map_err(|e| AnalysisError::Internal(format!("Unreadable yt-dlp info: {}", e)))?;
+
+    let format = pick_format(&info);
+    println!(
+        "[DEBUG] Probe: duration {:?}s, live {:?}, availability {:?}, format {:?}",
+        info.duration,
+        info.live_status,
+        info.availability,
+        format.map(|f| &f.format_id)
+    );
+    check(&info, format, config)?;
+
+    let metadata = VideoMetadata::from_info_json(&output.stdout).unwrap_or_else(|e| {
+        eprintln!("Warning: {:#}", e);
+        VideoMetadata::default()
+    });
+
+    Ok(Plan {
+        format: format.map(|f| {
+            if f.has_audio() {
+                f.format_id.clone()
+            } else {
+                // Video-only streams need the audio for whisper
+                format!("{}+bestaudio/{}", f.format_id, f.format_id)
+            }
+        }),
+        metadata,
+    })
+}
+
+// The smallest video format that is still sharp enough for frame analysis.
+// None when yt-dlp lists no formats with known dimensions, or none is big
+// enough; yt-dlp's own choice (see `platform::ytdlp_args`) is fine then.
+fn pick_format(info: &VideoInfo) -> Option<&Format> {
+    info.formats
+        .iter()
+        .filter(|f| f.has_video() && f.short_side().is_some_and(|s| s >= MIN_SHORT_SIDE))
+        .min_by(|a, b| {
+            let size = |f: &Format| f.size(info.duration).unwrap_or(f64::MAX);
+            size(a)
+                .total_cmp(&size(b))
+                .then(a.short_side().cmp(&b.short_side()))
+        })
+}
+
+fn check(info: &VideoInfo, format: Option<&Format>, config: &Config) -> Result<(), AnalysisError> {
+    let live_status = info.live_status.as_deref().unwrap_or("not_live");
+    if info.is_live == Some(true) || matches!(live_status, "is_live" | "is_upcoming") {
+        return Err(AnalysisError::Live(live_status.to_string()));
+    }
+
+    if let Some(availability) = info.availability.as_deref()
+        && matches!(
+            availability,
+            "private" | "premium_only" | "subscriber_only" | "needs_auth"
+        )
+    {
+        return Err(AnalysisError::Unavailable(format!(
+            "availability: {}",
+            availability
+        )));
+    }
+
+    if let (Some(max), Some(duration)) = (config.max_duration, info.duration)
+        && duration > max.as_secs_f64()
+    {
+        return Err(AnalysisError::TooBig(format!(
+            "it's {} long and I only watch up to {}",
+            human_duration(duration),
+            human_duration(max.as_secs_f64())
+        )));
+    }
+
+    let size = format
+        .and_then(|f| f.size(info.duration))
+        .or(info.filesize)
+        .or(info.filesize_approx);
+    if let (Some(max), Some(size)) = (config.max_filesize, size)
+        && size > max as f64
+    {
+        return Err(AnalysisError::TooBig(format!(
+            "it's about {} MB and my limit is {} MB",
+            (size / 1_000_000.0).round(),
+            max / 1_000_000
+        )));
+    }
+
+    Ok(())
+}
+
+// 4500 -> "1h 15m", 200 -> "3m 20s", 45 -> "45s"
+fn human_duration(secs: f64) -> String {
+    let secs = secs.round() as u64;
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match (h, m, s) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, 0) => format!("{}m", m),
+        (0, m, s) => format!("{}m {}s", m, s),
+        (h, 0, _) => format!("{}h", h),
+        (h, m, _) => format!("{}h {}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Trimmed from a real `yt-dlp --dump-json` of a TikTok
+    const TIKTOK: &str = r#"{
+        "id": "7301234567890123456",
+        "duration": 34,
+        "availability": null,
+        "live_status": "not_live",
+        "formats": [
+            {"format_id": "download", "width": 576, "height": 1024, "vcodec": "h264", "acodec": "aac", "filesize": 9800000},
+            {"format_id": "h264_540p_1", "width": 576, "height": 1024, "vcodec": "h264", "acodec": "aac", "filesize": 5200000, "tbr": 1220},
+            {"format_id": "bytevc1_540p_0", "width": 576, "height": 1024, "vcodec": "h265", "acodec": "aac", "filesize": 3100000, "tbr": 730},
+            {"format_id": "bytevc1_360p", "width": 320, "height": 568, "vcodec": "h265", "acodec": "aac", "filesize": 1400000},
+            {"format_id": "h264_1080p", "width": 1080, "height": 1920, "vcodec": "h264", "acodec": "aac", "filesize": 21000000}
+        ]
+    }"#;
+
+    fn info(json: &str) -> VideoInfo {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn limits() -> Config {
+        Config {
+            max_duration: Some(Duration::from_secs(600)),
+            max_filesize: Some(200_000_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn picks_the_smallest_format_that_is_good_enough() {
+        let tiktok = info(TIKTOK);
+        let format = pick_format(&tiktok).unwrap();
+        // 320 pixels wide is too small to read on-screen text
+        assert_eq!(format.format_id, "bytevc1_540p_0");
+        assert!(check(&tiktok, Some(format), &limits()).is_ok());
+
+        // Video-only streams are sized from their bitrate and get audio merged in
+        let dash = info(
+            r#"{"duration": 60, "formats": [
+                {"format_id": "137", "width": 1920, "height": 1080, "vcodec": "avc1", "acodec": "none", "tbr": 4400},
+                {"format_id": "135", "width": 854, "height": 480, "vcodec": "avc1", "acodec": "none", "tbr": 1100},
+                {"format_id": "140", "vcodec": "none", "acodec": "mp4a", "tbr": 129}
+            ]}"#,
+        );
+        assert_eq!(pick_format(&dash).unwrap().format_id, "135");
+    }
+
+    #[test]
+    fn enforces_duration_and_size_limits() {
+        let vod = info(r#"{"duration": 7384, "formats": []}"#);
+        let err = check(&vod, None, &limits()).unwrap_err();
+        assert!(matches!(err, AnalysisError::TooBig(_)));
+        assert!(err.user_message().contains("2h 3m long"));
+        assert!(err.user_message().contains("up to 10m"));
+
+        let huge = info(r#"{"duration": 300, "filesize_approx": 450000000}"#);
+        let err = check(&huge, None, &limits()).unwrap_err();
+        assert!(err.user_message().contains("450 MB"));
+
+        // No limits configured, anything goes
+        assert!(check(&vod, None, &Config::default()).is_ok());
+    }
+
+    #[test]
+    fn refuses_live_and_private_videos() {
+        let live = info(r#"{"is_live": true, "live_status": "is_live"}"#);
+        assert!(matches!(
+            check(&live, None, &limits()),
+            Err(AnalysisError::Live(_))
+        ));
+
+        let private = info(r#"{"duration": 20, "availability": "needs_auth"}"#);
+        assert!(matches!(
+            check(&private, None, &limits()),
+            Err(AnalysisError::Unavailable(_))
+        ));
+
+        let over = info(r#"{"duration": 20, "live_status": "was_live"}"#);
+        assert!(check(&over, None, &limits()).is_ok());
+    }
+
+    #[test]
+    fn formats_durations_for_people() {
+        assert_eq!(human_duration(45.0), "45s");
+        assert_eq!(human_duration(200.0), "3m 20s");
+        assert_eq!(human_duration(600.0), "10m");
+        assert_eq!(human_duration(7384.0), "2h 3m");
+    }
+}
@@ -0,0 +1,140 @@
+use crate::messenger::Messenger;
+use crate::rpc::{Quote, ReplyTarget};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// Signal clients hide the typing indicator after ~15s, so keep refreshing it
+const TYPING_REFRESH: Duration = Duration::from_secs(10);
+
+const REACTION_RECEIVED: &str = "⏳";
+const REACTION_DONE: &str = "✅";
+const REACTION_FAILED: &str = "❌";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Downloading,
+    Transcribing,
+    ExtractingFrames,
+    Summarizing,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Stage::Downloading => "Downloading video",
+            Stage::Transcribing => "Running Whisper fallback",
+            Stage::ExtractingFrames => "Extracting frames",
+            Stage::Summarizing => "Running Opencode analysis",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Handed to the pipeline so it can announce stage boundaries
+#[derive(Clone)]
+pub struct Progress {
+    stages: mpsc::UnboundedSender<Stage>,
+}
+
+impl Progress {
+    pub fn stage(&self, stage: Stage) {
+        println!("[DEBUG] {}...", stage);
+        let _ = self.stages.send(stage);
+    }
+}
+
+// Tell the sender we got their link, before it waits its turn in the queue
+pub async fn acknowledge<M: Messenger>(messenger: &M, target: &ReplyTarget, message: &Quote) {
+    react(messenger, target, message, REACTION_RECEIVED).await;
+}
+
+// The link was acknowledged but will never be analyzed
+pub async fn decline<M: Messenger>(messenger: &M, target: &ReplyTarget, message: &Quote) {
+    react(messenger, target, message, REACTION_FAILED).await;
+}
+
+// Replace the ⏳ with how it went
+pub async fn conclude<M: Messenger>(
+    messenger: &M,
+    target: &ReplyTarget,
+    message: &Quote,
+    success: bool,
+) {
+    let emoji = if success {
+        REACTION_DONE
+    } else {
+        REACTION_FAILED
+    };
+    react(messenger, target, message, emoji).await;
+}
+
+// Shows the sender that their link is being worked on: a typing indicator
+// for as long as the job runs, then a reaction with the outcome replacing
+// the ⏳ from `acknowledge` (unless there is no `message` to react to). Everything here is best effort; failures are
+// logged and otherwise ignored.
+pub struct Feedback<M: Messenger> {
+    messenger: Arc<M>,
+    target: ReplyTarget,
+    message: Option<Quote>,
+    typing: JoinHandle<()>,
+}
+
+impl<M: Messenger> Feedback<M> {
+    pub fn start(
+        messenger: Arc<M>,
+        target: ReplyTarget,
+        message: Option<Quote>,
+    ) -> (Self, Progress) {
+        let (tx, mut stages) = mpsc::unbounded_channel();
+        let typing = {
+            let messenger = messenger.clone();
+            let target = target.clone();
+            tokio::spawn(async move {
+                let mut ticker = tokio::time::interval(TYPING_REFRESH);
+                loop {
+                    // Stage boundaries are a good moment to re-assert the indicator
+                    tokio::select! {
+                        _ = ticker.tick() => {}
+                        Some(_) = stages.recv() => {}
+                    }
+                    if let Err(e) = messenger.send_typing(&target, false).await {
+                        println!("[DEBUG] Failed to send typing indicator: {}", e);
+                    }
+                }
+            })
+        };
+
+        let feedback = Self {
+            messenger,
+            target,
+            message,
+            typing,
+        };
+        (feedback, Progress { stages: tx })
+    }
+
+    pub async fn finish(self, success: bool) {
+        self.typing.abort();
+        if let Err(e) = self.messenger.send_typing(&self.target, true).await {
+            println!("[DEBUG] Failed to clear typing indicator: {}", e);
+        }
+        if let Some(ref message) = self.message {
+            conclude(&*self.messenger, &self.target, message, success).await;
+        }
+    }
+}
+
+async fn react<M: Messenger>(messenger: &M, target: &ReplyTarget, message: &Quote, emoji: &str) {
+    if let Err(e) = messenger.send_reaction(target, message, emoji).await {
+        println!("[DEBUG] Failed to react with {}: {}", emoji, e);
+    }
+}
+
+impl<M: Messenger> Drop for Feedback<M> {
+    fn drop(&mut self) {
+        self.typing.abort();
+    }
+}
@@ -0,0 +1,171 @@
+use crate::progress::Stage;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use tokio::sync::{Notify, Semaphore, SemaphorePermit};
+
+// Where a freshly queued job ended up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    // A worker is free, it starts right away
+    Started,
+    // Waiting behind others; 1 means next in line
+    Queued(usize),
+    // The queue is full
+    Rejected,
+}
+
+struct State<T> {
+    waiting: VecDeque<T>,
+    busy: usize,
+    closed: bool,
+}
+
+// FIFO of pending jobs shared by a fixed pool of workers. Bounded, so a
+// burst of links gets a polite "no" instead of swamping the box.
+pub struct JobQueue<T> {
+    state: Mutex<State<T>>,
+    notify: Notify,
+    workers: usize,
+    max_waiting: usize,
+}
+
+impl<T> JobQueue<T> {
+    pub fn new(workers: usize, max_waiting: usize) -> Self {
+        Self {
+            state: Mutex::new(State {
+                waiting: VecDeque::new(),
+                busy: 0,
+                closed: false,
+            }),
+            notify: Notify::new(),
+            workers: workers.max(1),
+            max_waiting,
+        }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    pub fn push(&self, job: T) -> Admission {
+        let mut state = self.state.lock().unwrap();
+        let idle = self.workers.saturating_sub(state.busy);
+        let ahead = state.waiting.len();
+
+        let admission = if ahead < idle {
+            Admission::Started
+        } else if ahead - idle >= self.max_waiting {
+            return Admission::Rejected;
+        } else {
+            Admission::Queued(ahead - idle + 1)
+        };
+
+        state.waiting.push_back(job);
+        drop(state);
+        self.notify.notify_one();
+        admission
+    }
+
+    // Put back a job from a previous run; never rejected, since it was
+    // already accepted once
+    pub fn requeue(&self, job: T) {
+        self.state.lock().unwrap().waiting.push_back(job);
+        self.notify.notify_one();
+    }
+
+    // Next job for a worker, or None once the queue is closed and drained.
+    // Callers must pair every job with a call to `done`.
+    pub async fn pop(&self) -> Option<T> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if let Some(job) = state.waiting.pop_front() {
+                    state.busy += 1;
+                    return Some(job);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    pub fn done(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.busy = state.busy.saturating_sub(1);
+    }
+
+    // Stop accepting work; workers exit once everything queued has run
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.notify.notify_waiters();
+    }
+}
+
+// Caps how many jobs may be inside a given pipeline stage at once, e.g. only
+// one whisper run at a time. `None` means unlimited.
+pub struct StageLimits {
+    downloads: Option<Semaphore>,
+    whisper: Option<Semaphore>,
+    ffmpeg: Option<Semaphore>,
+    opencode: Option<Semaphore>,
+}
+
+impl StageLimits {
+    pub fn new(
+        downloads: Option<usize>,
+        whisper: Option<usize>,
+        ffmpeg: Option<usize>,
+        opencode: Option<usize>,
+    ) -> Self {
+        let semaphore = |limit: Option<usize>| limit.map(|n| Semaphore::new(n.max(1)));
+        Self {
+            downloads: semaphore(downloads),
+            whisper: semaphore(whisper),
+            ffmpeg: semaphore(ffmpeg),
+            opencode: semaphore(opencode),
+        }
+    }
+
+    // Wait for a slot in `stage`; hold the permit for as long as the stage runs
+    pub async fn acquire(&self, stage: Stage) -> Option<SemaphorePermit<'_>> {
+        let semaphore = match stage {
+            Stage::Downloading => &self.downloads,
+            Stage::Transcribing => &self.whisper,
+            Stage::ExtractingFrames => &self.ffmpeg,
+            Stage::Summarizing => &self.opencode,
+        };
+        match semaphore {
+            Some(semaphore) => semaphore.acquire().await.ok(),
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reports_positions_and_rejects_when_full() {
+        let queue = JobQueue::new(1, 2);
+
+        assert_eq!(queue.push("a"), Admission::Started);
+        assert_eq!(queue.pop().await, Some("a"));
+
+        assert_eq!(queue.push("b"), Admission::Queued(1));
+        assert_eq!(queue.push("c"), Admission::Queued(2));
+        assert_eq!(queue.push("d"), Admission::Rejected);
+
+        queue.done();
+        assert_eq!(queue.pop().await, Some("b"));
+        assert_eq!(queue.push("e"), Admission::Queued(2));
+
+        queue.close();
+        assert_eq!(queue.pop().await, Some("c"));
+        assert_eq!(queue.pop().await, Some("e"));
+        assert_eq!(queue.pop().await, None);
+    }
+}
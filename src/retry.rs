@@ -0,0 +1,190 @@
+use crate::error::AnalysisError;
+use anyhow::{Result, bail};
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::time::Duration;
+
+// How often a flaky stage is tried, and which failures are worth another go.
+// The default never retries.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryPolicy {
+    // Including the first try; 1 means never retry
+    pub max_attempts: u32,
+    // Wait before the first retry, doubled for every one after it
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // `AnalysisError::kind` names that get retried
+    pub retry_on: Vec<String>,
+}
+
+impl RetryPolicy {
+    // Accepts a comma-separated list of error kinds, e.g. "download_blocked,timeout"
+    pub fn parse_kinds(value: &str) -> Result<Vec<String>> {
+        let mut kinds = Vec::new();
+        for kind in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+            if !AnalysisError::KINDS.contains(&kind) {
+                bail!(
+                    "unknown error kind '{}', expected some of: {}",
+                    kind,
+                    AnalysisError::KINDS.join(", ")
+                );
+            }
+            kinds.push(kind.to_string());
+        }
+        Ok(kinds)
+    }
+
+    fn retries(&self, error: &AnalysisError) -> bool {
+        self.retry_on.iter().any(|kind| kind == error.kind())
+    }
+
+    // Exponential backoff with jitter: somewhere between half and all of
+    // base * 2^(retry - 1), capped at max_delay, so jobs that failed together
+    // don't all hit the site again at the same moment
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let full = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        let half = full / 2;
+        let jitter = RandomState::new().hash_one((retry, std::time::Instant::now()));
+        half + half.mul_f64((jitter % 1000) as f64 / 1000.0)
+    }
+}
+
+// Run `attempt` until it succeeds, fails with a non-retryable error or runs
+// out of attempts. Every failed attempt is logged.
+pub async fn retry<T, F, Fut>(
+    policy: &RetryPolicy,
+    stage: &str,
+    mut attempt: F,
+) -> Result<T, AnalysisError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, AnalysisError>>,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut tried = 1;
+    loop {
+        match attempt().await {
+            Ok(value) => {
+                if tried > 1 {
+                    println!("🔁 {} succeeded on attempt {}", stage, tried);
+                }
+                return Ok(value);
+            }
+            Err(e) if tried < max_attempts && policy.retries(&e) => {
+                let delay = policy.delay(tried);
+                println!(
+                    "🔁 {} attempt {}/{} failed ({}), retrying in {:.1}s: {}",
+                    stage,
+                    tried,
+                    max_attempts,
+                    e.kind(),
+                    delay.as_secs_f64(),
+                    e
+                );
+                tokio::time::sleep(delay).await;
+                tried += 1;
+            }
+            Err(e) => {
+                if tried > 1 {
+                    println!(
+                        "🔁 {} giving up after attempt {}/{} ({})",
+                        stage,
+                        tried,
+                        max_attempts,
+                        e.kind()
+                    );
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            retry_on: vec!["download_blocked".to_string(), "timeout".to_string()],
+        }
+    }
+
+    // Fails with the given errors in turn, then succeeds
+    async fn run(
+        policy: &RetryPolicy,
+        mut errors: Vec<AnalysisError>,
+    ) -> (Result<u32, AnalysisError>, u32) {
+        let calls = AtomicU32::new(0);
+        errors.reverse();
+        let result = retry(policy, "test", || {
+            let n = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            let next = errors.pop();
+            async move {
+                match next {
+                    Some(e) => Err(e),
+                    None => Ok(n),
+                }
+            }
+        })
+        .await;
+        (result, calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let errors = vec![
+            AnalysisError::DownloadBlocked("HTTP Error 429".to_string()),
+            AnalysisError::Timeout("yt-dlp".to_string()),
+        ];
+        let (result, calls) = run(&policy(3), errors).await;
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(calls, 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_permanent_failures_and_after_max_attempts() {
+        let errors = vec![AnalysisError::Unavailable("private".to_string())];
+        let (result, calls) = run(&policy(3), errors).await;
+        assert!(matches!(result, Err(AnalysisError::Unavailable(_))));
+        assert_eq!(calls, 1);
+
+        let errors = vec![
+            AnalysisError::DownloadBlocked("403".to_string()),
+            AnalysisError::DownloadBlocked("403".to_string()),
+            AnalysisError::DownloadBlocked("403".to_string()),
+        ];
+        let (result, calls) = run(&policy(2), errors).await;
+        assert!(matches!(result, Err(AnalysisError::DownloadBlocked(_))));
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn backs_off_exponentially_with_jitter() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+            ..policy(5)
+        };
+        for (retry, full) in [(1, 2), (2, 4), (3, 8), (4, 16), (5, 30), (9, 30)] {
+            let delay = policy.delay(retry);
+            let full = Duration::from_secs(full);
+            assert!(delay >= full / 2 && delay <= full, "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn parses_error_kinds() {
+        assert_eq!(
+            RetryPolicy::parse_kinds("timeout, llm_failed").unwrap(),
+            ["timeout", "llm_failed"]
+        );
+        assert!(RetryPolicy::parse_kinds("flaky").is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+
+// How long we wait for signal-cli to answer a request before giving up on it
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+
+// signal-cli uses this error code when the server rate-limits us
+const RATE_LIMIT_ERROR_CODE: i64 = -5;
+
+// --- Wire Structs ---
+#[derive(Serialize, Debug)]
+struct JsonRpcRequest<P: Serialize> {
+    jsonrpc: &'static str,
+    method: &'static str,
+    params: P,
+    id: String,
+}
+
+#[derive(Serialize, Debug)]
+struct SendParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient: Option<Vec<&'a str>>,
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    group_id: Option<&'a str>,
+    message: &'a str,
+    #[serde(rename = "quoteTimestamp", skip_serializing_if = "Option::is_none")]
+    quote_timestamp: Option<u64>,
+    #[serde(rename = "quoteAuthor", skip_serializing_if = "Option::is_none")]
+    quote_author: Option<&'a str>,
+    #[serde(rename = "quoteMessage", skip_serializing_if = "Option::is_none")]
+    quote_message: Option<&'a str>,
+    // Paths signal-cli uploads along with the message
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    attachments: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct ReactionParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient: Option<Vec<&'a str>>,
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    group_id: Option<&'a str>,
+    emoji: &'a str,
+    #[serde(rename = "targetAuthor")]
+    target_author: &'a str,
+    #[serde(rename = "targetTimestamp")]
+    target_timestamp: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct TypingParams<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipient: Option<Vec<&'a str>>,
+    #[serde(rename = "groupId", skip_serializing_if = "Option::is_none")]
+    group_id: Option<&'a str>,
+    stop: bool,
+}
+
+// The message a reply is threaded under, identified by its sent timestamp and author
+#[derive(Debug, Clone, PartialEq)]
+pub struct Quote {
+    pub timestamp: u64,
+    pub author: String,
+    pub message: String,
+}
+
+// Where a reply goes: a single person, or back into a group chat
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReplyTarget {
+    Direct(String),
+    Group(String),
+}
+
+impl ReplyTarget {
+    // Split into the mutually exclusive `recipient` / `groupId` params
+    fn params(&self) -> (Option<Vec<&str>>, Option<&str>) {
+        match self {
+            ReplyTarget::Direct(recipient) => (Some(vec![recipient.as_str()]), None),
+            ReplyTarget::Group(group_id) => (None, Some(group_id.as_str())),
+        }
+    }
+}
+
+impl fmt::Display for ReplyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplyTarget::Direct(recipient) => write!(f, "{}", recipient),
+            ReplyTarget::Group(group_id) => write!(f, "group {}", group_id),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SendResult {
+    timestamp: Option<u64>,
+    #[serde(default)]
+    results: Vec<RecipientResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecipientResult {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+// --- Errors ---
+#[derive(Debug)]
+pub enum RpcError {
+    // The writer task or signal-cli went away before we got an answer
+    Closed,
+    // signal-cli did not answer within RESPONSE_TIMEOUT
+    Timeout,
+    // The server (or a recipient) is rate-limiting us
+    RateLimited(String),
+    // signal-cli answered with a JSON-RPC error object
+    Server { code: i64, message: String },
+    // The request went through but delivery to the recipient failed
+    Delivery(String),
+    // The response did not look like what we expected
+    Malformed(String),
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RpcError::Closed => write!(f, "signal-cli connection closed"),
+            RpcError::Timeout => write!(f, "timed out waiting for signal-cli"),
+            RpcError::RateLimited(msg) => write!(f, "rate limited: {}", msg),
+            RpcError::Server { code, message } => {
+                write!(f, "signal-cli error {}: {}", code, message)
+            }
+            RpcError::Delivery(kind) => write!(f, "delivery failed: {}", kind),
+            RpcError::Malformed(msg) => write!(f, "malformed response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+type Pending = oneshot::Sender<Result<Value, RpcError>>;
+
+// --- Client ---
+// Correlates requests written to signal-cli with the responses it writes back.
+// Lines are handed to a writer task through `outgoing`; the reader loop feeds
+// every response it sees into `resolve`. Requests wait while the transport is
+// down, so callers can outlive a signal-cli restart.
+pub struct RpcClient {
+    next_id: AtomicU64,
+    pending: Mutex<HashMap<String, Pending>>,
+    outgoing: mpsc::Sender<String>,
+    connected: watch::Sender<bool>,
+}
+
+impl RpcClient {
+    pub fn new(outgoing: mpsc::Sender<String>) -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            pending: Mutex::new(HashMap::new()),
+            outgoing,
+            connected: watch::Sender::new(false),
+        }
+    }
+
+    // Called by the supervisor. Losing the connection fails every in-flight
+    // request with `Closed`, since signal-cli will never answer them.
+    pub fn set_connected(&self, connected: bool) {
+        self.connected.send_replace(connected);
+        if !connected {
+            for (_, waiter) in self.pending.lock().unwrap().drain() {
+                let _ = waiter.send(Err(RpcError::Closed));
+            }
+        }
+    }
+
+    // Send a text message, optionally as a quote-reply and with files
+    // attached, returning the server timestamp of the sent message
+    pub async fn send(
+        &self,
+        target: &ReplyTarget,
+        message: &str,
+        quote: Option<&Quote>,
+        attachments: &[PathBuf],
+    ) -> Result<u64, RpcError> {
+        let (recipient, group_id) = target.params();
+        let params = SendParams {
+            recipient,
+            group_id,
+            message,
+            quote_timestamp: quote.map(|q| q.timestamp),
+            quote_author: quote.map(|q| q.author.as_str()),
+            quote_message: quote.map(|q| q.message.as_str()),
+            attachments: attachments
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect(),
+        };
+        let value = self.request("send", params).await?;
+
+        let result: SendResult =
+            serde_json::from_value(value).map_err(|e| RpcError::Malformed(e.to_string()))?;
+
+        // signal-cli reports per-recipient failures inside a successful response
+        if let Some(failed) = result.results.iter().find(|r| r.kind != "SUCCESS") {
+            return Err(if failed.kind == "RATE_LIMIT_FAILURE" {
+                RpcError::RateLimited(failed.kind.clone())
+            } else {
+                RpcError::Delivery(failed.kind.clone())
+            });
+        }
+
+        result
+            .timestamp
+            .ok_or_else(|| RpcError::Malformed("missing timestamp".to_string()))
+    }
+
+    // React to `message` (identified by its author and timestamp) with `emoji`.
+    // Reacting again with a different emoji replaces the previous reaction.
+    pub async fn send_reaction(
+        &self,
+        target: &ReplyTarget,
+        message: &Quote,
+        emoji: &str,
+    ) -> Result<(), RpcError> {
+        let (recipient, group_id) = target.params();
+        let params = ReactionParams {
+            recipient,
+            group_id,
+            emoji,
+            target_author: &message.author,
+            target_timestamp: message.timestamp,
+        };
+        self.request("sendReaction", params).await.map(|_| ())
+    }
+
+    // Show (or clear, with `stop`) the "typing..." indicator
+    pub async fn send_typing(&self, target: &ReplyTarget, stop: bool) -> Result<(), RpcError> {
+        let (recipient, group_id) = target.params();
+        let params = TypingParams {
+            recipient,
+            group_id,
+            stop,
+        };
+        self.request("sendTyping", params).await.map(|_| ())
+    }
+
+    pub async fn request<P: Serialize>(
+        &self,
+        method: &'static str,
+        params: P,
+    ) -> Result<Value, RpcError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+        let payload = JsonRpcRequest {
+            jsonrpc: "2.0",
+            method,
+            params,
+            id: id.clone(),
+        };
+        let line =
+            serde_json::to_string(&payload).map_err(|e| RpcError::Malformed(e.to_string()))?;
+
+        // Hold the request until signal-cli is (back) up
+        let mut state = self.connected.subscribe();
+        if state.wait_for(|up| *up).await.is_err() {
+            return Err(RpcError::Closed);
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id.clone(), tx);
+
+        if self.outgoing.send(line).await.is_err() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(RpcError::Closed);
+        }
+
+        match tokio::time::timeout(RESPONSE_TIMEOUT, rx).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(_)) => Err(RpcError::Closed),
+            Err(_) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(RpcError::Timeout)
+            }
+        }
+    }
+
+    // A request line that never reached signal-cli. Its caller gets `Closed`
+    // instead of waiting out RESPONSE_TIMEOUT, so it can be retried.
+    pub fn abandon(&self, line: &str) {
+        let Ok(request) = serde_json::from_str::<Value>(line) else {
+            return;
+        };
+        let Some(id) = request.get("id").and_then(Value::as_str) else {
+            return;
+        };
+        if let Some(waiter) = self.pending.lock().unwrap().remove(id) {
+            let _ = waiter.send(Err(RpcError::Closed));
+        }
+    }
+
+    // Complete the pending request matching `id`. Returns false if nobody was waiting for it.
+    pub fn resolve(&self, id: &Value, result: Option<Value>, error: Option<ErrorObject>) -> bool {
+        let key = match id {
+            Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let Some(waiter) = self.pending.lock().unwrap().remove(&key) else {
+            return false;
+        };
+
+        let outcome = match error {
+            Some(err) if err.code == RATE_LIMIT_ERROR_CODE => {
+                Err(RpcError::RateLimited(err.message))
+            }
+            Some(err) => Err(RpcError::Server {
+                code: err.code,
+                message: err.message,
+            }),
+            None => Ok(result.unwrap_or(Value::Null)),
+        };
+        let _ = waiter.send(outcome);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    // A connected client and the lines it writes
+    fn client() -> (Arc<RpcClient>, mpsc::Receiver<String>) {
+        let (tx, rx) = mpsc::channel(8);
+        let client = Arc::new(RpcClient::new(tx));
+        client.set_connected(true);
+        (client, rx)
+    }
+
+    // The id of the next request the client writes
+    async fn next_id(outgoing: &mut mpsc::Receiver<String>) -> Value {
+        let line = outgoing.recv().await.unwrap();
+        let request: Value = serde_json::from_str(&line).unwrap();
+        request["id"].clone()
+    }
+
+    fn target() -> ReplyTarget {
+        ReplyTarget::Direct("+4512345678".to_string())
+    }
+
+    #[tokio::test]
+    async fn matches_responses_to_requests_by_id() {
+        let (client, mut outgoing) = client();
+        let first = tokio::spawn({
+            let client = client.clone();
+            async move { client.request("listGroups", ()).await }
+        });
+        let first_id = next_id(&mut outgoing).await;
+        let second = tokio::spawn({
+            let client = client.clone();
+            async move { client.request("listContacts", ()).await }
+        });
+        let second_id = next_id(&mut outgoing).await;
+        assert_ne!(first_id, second_id);
+
+        // Answered out of order
+        assert!(client.resolve(&second_id, Some(Value::from("contacts")), None));
+        assert!(client.resolve(&first_id, Some(Value::from("groups")), None));
+        assert!(!client.resolve(&first_id, None, None));
+
+        assert_eq!(first.await.unwrap().unwrap(), "groups");
+        assert_eq!(second.await.unwrap().unwrap(), "contacts");
+    }
+
+    #[tokio::test]
+    async fn maps_error_objects() {
+        let (client, mut outgoing) = client();
+        let error = |code: i64| ErrorObject {
+            code,
+            message: "nope".to_string(),
+        };
+
+        let limited = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_typing(&target(), false).await }
+        });
+        let id = next_id(&mut outgoing).await;
+        client.resolve(&id, None, Some(error(RATE_LIMIT_ERROR_CODE)));
+        assert!(matches!(
+            limited.await.unwrap(),
+            Err(RpcError::RateLimited(_))
+        ));
+
+        let failed = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_typing(&target(), false).await }
+        });
+        let id = next_id(&mut outgoing).await;
+        client.resolve(&id, None, Some(error(-1)));
+        assert!(matches!(
+            failed.await.unwrap(),
+            Err(RpcError::Server { code: -1, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn reports_per_recipient_failures() {
+        let (client, mut outgoing) = client();
+        let send = |client: &Arc<RpcClient>| {
+            let client = client.clone();
+            tokio::spawn(async move { client.send(&target(), "hi", None, &[]).await })
+        };
+        let result = |kind: &str| serde_json::json!({ "timestamp": 1700000000000u64, "results": [{ "type": kind }] });
+
+        let limited = send(&client);
+        let id = next_id(&mut outgoing).await;
+        client.resolve(&id, Some(result("RATE_LIMIT_FAILURE")), None);
+        assert!(matches!(
+            limited.await.unwrap(),
+            Err(RpcError::RateLimited(kind)) if kind == "RATE_LIMIT_FAILURE"
+        ));
+
+        let unregistered = send(&client);
+        let id = next_id(&mut outgoing).await;
+        client.resolve(&id, Some(result("UNREGISTERED_FAILURE")), None);
+        assert!(matches!(
+            unregistered.await.unwrap(),
+            Err(RpcError::Delivery(_))
+        ));
+
+        let sent = send(&client);
+        let id = next_id(&mut outgoing).await;
+        client.resolve(&id, Some(result("SUCCESS")), None);
+        assert_eq!(sent.await.unwrap().unwrap(), 1700000000000);
+    }
+
+    #[tokio::test]
+    async fn fails_abandoned_requests() {
+        let (client, mut outgoing) = client();
+        let pending = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_typing(&target(), true).await }
+        });
+        let line = outgoing.recv().await.unwrap();
+        client.abandon(&line);
+        assert!(matches!(pending.await.unwrap(), Err(RpcError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn fails_in_flight_requests_on_disconnect() {
+        let (client, mut outgoing) = client();
+        let pending = tokio::spawn({
+            let client = client.clone();
+            async move { client.send_typing(&target(), true).await }
+        });
+        next_id(&mut outgoing).await;
+        client.set_connected(false);
+        assert!(matches!(pending.await.unwrap(), Err(RpcError::Closed)));
+    }
+}
@@ -0,0 +1,256 @@
+use crate::error::AnalysisError;
+use crate::frames::Frame;
+use crate::pipeline::output_within;
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+// Width of one frame on a sheet; height follows the video's aspect ratio
+const CELL_WIDTH: u32 = 360;
+
+// Contact sheets: the sampled frames tiled into a few labeled grids, so
+// opencode looks at one image per nine frames instead of nine images
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sheets {
+    pub enabled: bool,
+    pub columns: usize,
+    pub rows: usize,
+    // Send the sheets along with the summary as a preview
+    pub attach: bool,
+}
+
+impl Default for Sheets {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            columns: 3,
+            rows: 3,
+            attach: true,
+        }
+    }
+}
+
+// Tile `frames` into sheets/sheet_01.jpg onwards, each frame labeled with
+// where in the video it was taken
+pub async fn build(
+    work_dir: &Path,
+    frames: &[Frame],
+    sheets: &Sheets,
+    limit: Duration,
+) -> Result<Vec<PathBuf>, AnalysisError> {
+    let sheets_dir = work_dir.join("sheets");
+    fs::create_dir_all(&sheets_dir).context("Failed to create sheets directory")?;
+
+    let per_sheet = (sheets.columns * sheets.rows).max(1);
+    let mut built = Vec::new();
+    for (n, chunk) in frames.chunks(per_sheet).enumerate() {
+        let path = sheets_dir.join(format!("sheet_{:02}.jpg", n + 1));
+        let first = n * per_sheet;
+
+        let mut command = Command::new("ffmpeg");
+        command.current_dir(work_dir).args(["-v", "error", "-y"]);
+        for frame in chunk {
+            command.arg("-i").arg(&frame.path);
+        }
+        command
+            .args(["-filter_complex", &filter(chunk, first, sheets.columns)])
+            .args(["-map", "[sheet]", "-frames:v", "1", "-q:v", "3"])
+            .arg(&path)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let output = output_within(&mut command, "ffmpeg", limit)
+            .await?
+            .context("Failed to run ffmpeg")?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(AnalysisError::Internal(format!(
+                "ffmpeg failed to build {}: {}",
+                path.display(),
+                stderr
+            )));
+        }
+        built.push(path);
+    }
+
+    println!(
+        "🖼️ Tiled {} frames into {} contact sheet(s)",
+        frames.len(),
+        built.len()
+    );
+    Ok(built)
+}
+
+// Scale and label every input, then stack them left to right, top to bottom.
+// `first` is the index of chunk[0] among all frames, for unlabeled frames.
+fn filter(chunk: &[Frame], first: usize, columns: usize) -> String {
+    let columns = columns.max(1);
+    let mut parts: Vec<String> = chunk
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            format!(
+                "[{i}:v]scale={CELL_WIDTH}:-2,drawtext=text='{}':x=8:y=8:fontsize=28:\
+                 fontcolor=white:box=1:boxcolor=black@0.6:boxborderw=6[f{i}]",
+                label(frame.timestamp, first + i)
+            )
+        })
+        .collect();
+
+    if chunk.len() == 1 {
+        parts.push("[f0]null[sheet]".to_string());
+    } else {
+        let inputs: String = (0..chunk.len()).map(|i| format!("[f{}]", i)).collect();
+        let layout = (0..chunk.len())
+            .map(|i| {
+                format!(
+                    "{}_{}",
+                    offset("w0", i % columns),
+                    offset("h0", i / columns)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("|");
+        parts.push(format!(
+            "{}xstack=inputs={}:layout={}:fill=black[sheet]",
+            inputs,
+            chunk.len(),
+            layout
+        ));
+    }
+    parts.join(";")
+}
+
+// xstack position of the n-th cell along one axis: 0, w0, w0+w0, ...
+fn offset(size: &str, n: usize) -> String {
+    if n == 0 {
+        "0".to_string()
+    } else {
+        vec![size; n].join("+")
+    }
+}
+
+// "4s", "1m05s"; drawtext would need colons escaped. Frames without a
+// timestamp get their number instead.
+fn label(timestamp: Option<f64>, index: usize) -> String {
+    match timestamp {
+        Some(t) => {
+            let secs = t.round() as u64;
+            if secs < 60 {
+                format!("{}s", secs)
+            } else {
+                format!("{}m{:02}s", secs / 60, secs % 60)
+            }
+        }
+        None => format!("#{}", index + 1),
+    }
+}
+
+// Sheets outlive the job's workspace until they've been sent with the reply.
+// The pipeline parks them in `dir`; once the job has an id they're renamed
+// to job-<id>-<n>.jpg, so a reply re-sent after a restart still finds them.
+pub fn keep_previews(dir: &Path, id: i64, sheets: &[PathBuf]) -> Result<()> {
+    for (n, sheet) in sheets.iter().enumerate() {
+        let dest = dir.join(format!("job-{}-{}.jpg", id, n + 1));
+        fs::rename(sheet, &dest)
+            .with_context(|| format!("Failed to keep preview {}", sheet.display()))?;
+    }
+    Ok(())
+}
+
+pub fn previews(dir: &Path, id: i64) -> Vec<PathBuf> {
+    let prefix = format!("job-{}-", id);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|n| n.starts_with(&prefix))
+        })
+        .collect();
+    paths.sort_by_key(|p| (p.as_os_str().len(), p.clone()));
+    paths
+}
+
+pub fn discard_previews(dir: &Path, id: i64) {
+    for path in previews(dir, id) {
+        if let Err(e) = fs::remove_file(&path) {
+            eprintln!(
+                "Warning: Failed to remove preview {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(timestamps: &[Option<f64>]) -> Vec<Frame> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, &timestamp)| Frame {
+                path: PathBuf::from(format!("frames/frame_{:03}.jpg", i + 1)),
+                timestamp,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn labels_frames_with_their_timestamps() {
+        assert_eq!(label(Some(0.0), 0), "0s");
+        assert_eq!(label(Some(4.4), 1), "4s");
+        assert_eq!(label(Some(65.0), 2), "1m05s");
+        assert_eq!(label(None, 2), "#3");
+    }
+
+    #[test]
+    fn lays_frames_out_in_a_grid() {
+        let chunk = frames(&[Some(0.0), Some(2.0), Some(4.0), Some(6.0), Some(8.0)]);
+        let filter = filter(&chunk, 0, 3);
+        assert!(filter.contains("[1:v]scale=360:-2,drawtext=text='2s'"));
+        assert!(filter.ends_with(
+            "[f0][f1][f2][f3][f4]xstack=inputs=5:layout=0_0|w0_0|w0+w0_0|0_h0|w0_h0:fill=black[sheet]"
+        ));
+    }
+
+    #[test]
+    fn a_lone_frame_is_its_own_sheet() {
+        let filter = filter(&frames(&[None]), 9, 3);
+        assert!(filter.contains("text='#10'"));
+        assert!(filter.ends_with("[f0]null[sheet]"));
+    }
+
+    #[test]
+    fn keeps_previews_until_discarded() {
+        let dir = std::env::temp_dir().join(format!("brainrot-sheets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let park = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        let sheets = [park("pending-a-1.jpg", "1"), park("pending-a-2.jpg", "2")];
+        let other = [park("pending-b-1.jpg", "1")];
+
+        keep_previews(&dir, 7, &sheets).unwrap();
+        keep_previews(&dir, 17, &other).unwrap();
+        let kept = previews(&dir, 7);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(fs::read_to_string(&kept[1]).unwrap(), "2");
+
+        discard_previews(&dir, 7);
+        assert!(previews(&dir, 7).is_empty());
+        assert_eq!(previews(&dir, 17).len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,406 @@
+use crate::config::Transport;
+use crate::envelope::Envelope;
+use crate::messenger::Messenger;
+use crate::rpc::{ErrorObject, Quote, ReplyTarget, RpcClient, RpcError};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::process::{Child, Command};
+use tokio::sync::{Mutex, mpsc};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// A session that stayed up at least this long resets the backoff
+const HEALTHY_SESSION: Duration = Duration::from_secs(60);
+
+// --- JSON-RPC Structs ---
+// Every line signal-cli prints: either a response to one of our requests
+// (has an `id`) or a notification such as `receive`.
+#[derive(Deserialize, Debug)]
+struct RpcResponse {
+    id: Option<serde_json::Value>,
+    method: Option<String>,
+    params: Option<RpcParams>,
+    result: Option<serde_json::Value>,
+    error: Option<ErrorObject>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RpcParams {
+    envelope: Option<Envelope>,
+}
+
+type Reader = Box<dyn AsyncRead + Unpin + Send>;
+type Writer = Box<dyn AsyncWrite + Unpin + Send>;
+
+// One live link to signal-cli. `child` is only set when we spawned it
+// ourselves; dropping it kills the process.
+struct Connection {
+    reader: Reader,
+    writer: Writer,
+    child: Option<Child>,
+}
+
+// The real messenger: signal-cli over JSON-RPC, kept alive by `supervise`
+pub struct SignalCli {
+    client: Arc<RpcClient>,
+    incoming: Mutex<mpsc::UnboundedReceiver<Envelope>>,
+}
+
+impl SignalCli {
+    pub fn start(transport: Transport) -> Self {
+        // Channels between the supervisor and the rest of the bot. Envelopes
+        // are unbounded so a slow event loop never holds up the responses
+        // behind them.
+        let (out_tx, out_rx) = mpsc::channel::<String>(32);
+        let (in_tx, in_rx) = mpsc::unbounded_channel::<Envelope>();
+        let client = Arc::new(RpcClient::new(out_tx));
+
+        // Keep a signal-cli connection open (own child or shared daemon), reconnecting if it drops
+        tokio::spawn(supervise(transport, client.clone(), out_rx, in_tx));
+
+        Self {
+            client,
+            incoming: Mutex::new(in_rx),
+        }
+    }
+}
+
+impl Messenger for SignalCli {
+    async fn receive(&self) -> Option<Envelope> {
+        self.incoming.lock().await.recv().await
+    }
+
+    async fn send(
+        &self,
+        target: &ReplyTarget,
+        message: &str,
+        quote: Option<&Quote>,
+        attachments: &[PathBuf],
+    ) -> Result<u64, RpcError> {
+        self.client.send(target, message, quote, attachments).await
+    }
+
+    async fn send_reaction(
+        &self,
+        target: &ReplyTarget,
+        message: &Quote,
+        emoji: &str,
+    ) -> Result<(), RpcError> {
+        self.client.send_reaction(target, message, emoji).await
+    }
+
+    async fn send_typing(&self, target: &ReplyTarget, stop: bool) -> Result<(), RpcError> {
+        self.client.send_typing(target, stop).await
+    }
+}
+
+// Keeps a signal-cli connection open for the lifetime of the bot.
+// Responses signal-cli prints are handed to `client`, received envelopes go
+// to `incoming`; every line queued on `outgoing` is written to it. When the child exits (or the daemon drops the
+// socket) we fail in-flight requests, wait with exponential backoff and
+// reconnect, re-using the same channels so the rest of the bot never notices.
+async fn supervise(
+    transport: Transport,
+    client: Arc<RpcClient>,
+    mut outgoing: mpsc::Receiver<String>,
+    incoming: mpsc::UnboundedSender<Envelope>,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+
+    loop {
+        let started = Instant::now();
+        match connect(&transport).await {
+            Ok(conn) => {
+                println!("✅ Connected to signal-cli ({:?})", transport);
+                client.set_connected(true);
+                let reason = run_session(conn, &client, &mut outgoing, &incoming).await;
+                client.set_connected(false);
+                eprintln!("❌ signal-cli session ended: {}", reason);
+            }
+            Err(e) => eprintln!("❌ {:#}", e),
+        }
+
+        if incoming.is_closed() {
+            return;
+        }
+
+        // Anything still queued was never written; fail it so the caller
+        // retries instead of waiting for an answer that can't come
+        while let Ok(line) = outgoing.try_recv() {
+            client.abandon(&line);
+        }
+
+        if started.elapsed() >= HEALTHY_SESSION {
+            backoff = INITIAL_BACKOFF;
+        }
+        println!("[DEBUG] Reconnecting to signal-cli in {:?}...", backoff);
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(transport: &Transport) -> Result<Connection> {
+    match transport {
+        Transport::Spawn => {
+            let mut child = Command::new("signal-cli")
+                .args(["--output=json", "jsonRpc"])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .kill_on_drop(true)
+                .spawn()
+                .context("Failed to spawn signal-cli")?;
+            let stdout = child.stdout.take().context("No stdout")?;
+            let stdin = child.stdin.take().context("No stdin")?;
+            Ok(Connection {
+                reader: Box::new(stdout),
+                writer: Box::new(stdin),
+                child: Some(child),
+            })
+        }
+        Transport::Unix(path) => {
+            let stream = UnixStream::connect(path)
+                .await
+                .with_context(|| format!("Failed to connect to {}", path.display()))?;
+            let (reader, writer) = stream.into_split();
+            Ok(Connection {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+                child: None,
+            })
+        }
+        Transport::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("Failed to connect to {}", addr))?;
+            let (reader, writer) = stream.into_split();
+            Ok(Connection {
+                reader: Box::new(reader),
+                writer: Box::new(writer),
+                child: None,
+            })
+        }
+    }
+}
+
+// Pump lines in both directions until the connection goes away. Returns why it ended.
+async fn run_session(
+    conn: Connection,
+    client: &RpcClient,
+    outgoing: &mut mpsc::Receiver<String>,
+    incoming: &mpsc::UnboundedSender<Envelope>,
+) -> String {
+    let Connection {
+        reader,
+        mut writer,
+        child,
+    } = conn;
+    let mut reader = BufReader::new(reader).lines();
+
+    loop {
+        tokio::select! {
+            line = reader.next_line() => match line {
+                Ok(Some(line)) => {
+                    if let Some(envelope) = dispatch(&line, client)
+                        && incoming.send(envelope).is_err()
+                    {
+                        return "event loop shut down".to_string();
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => return format!("read error: {}", e),
+            },
+            Some(mut line) = outgoing.recv() => {
+                line.push('\n'); // Newline is critical for JSON-RPC
+                let written = async {
+                    writer.write_all(line.as_bytes()).await?;
+                    writer.flush().await
+                };
+                // The connection is broken; ending the session fails the
+                // request straight away instead of leaving it to time out
+                if let Err(e) = written.await {
+                    return format!("write error: {}", e);
+                }
+            }
+        }
+    }
+
+    match child {
+        Some(mut child) => match child.wait().await {
+            Ok(status) => format!("signal-cli exited with {}", status),
+            Err(e) => format!("stdout closed ({})", e),
+        },
+        None => "daemon closed the connection".to_string(),
+    }
+}
+
+// Resolve a response to one of our requests, or pick the envelope out of a
+// `receive` notification. Anything else is logged and dropped.
+fn dispatch(line: &str, client: &RpcClient) -> Option<Envelope> {
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    // Debug: Print raw JSON (truncated for readability)
+    let preview = if line.len() > 300 {
+        &line[..line.floor_char_boundary(300)]
+    } else {
+        line
+    };
+    println!("[DEBUG] Raw JSON: {}...", preview);
+
+    // Parse JSON-RPC wrapper
+    let rpc_msg: RpcResponse = match serde_json::from_str(line) {
+        Ok(m) => m,
+        Err(e) => {
+            if line.trim().starts_with('{') {
+                println!("[DEBUG] JSON parse error: {}", e);
+            }
+            return None;
+        }
+    };
+
+    // Responses to our own requests carry the id we sent
+    if let Some(id) = rpc_msg.id {
+        if !client.resolve(&id, rpc_msg.result, rpc_msg.error) {
+            println!("[DEBUG] Response for unknown request id: {}", id);
+        }
+        return None;
+    }
+
+    // We only care about "receive" methods
+    let method = rpc_msg.method.as_deref();
+    if method != Some("receive") {
+        println!("[DEBUG] Skipping method: {:?}", method);
+        return None;
+    }
+
+    let Some(params) = rpc_msg.params else {
+        println!("[DEBUG] No params in message");
+        return None;
+    };
+    if params.envelope.is_none() {
+        println!("[DEBUG] No envelope in params");
+    }
+    params.envelope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::ReplyTarget;
+    use serde_json::Value;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::net::{TcpListener, UnixListener};
+
+    // Plays the daemon side of one connection: answers the first request with
+    // a send result, then pushes a receive notification.
+    async fn fake_daemon<S: AsyncRead + AsyncWrite + Unpin>(stream: S) {
+        let (read, mut write) = tokio::io::split(stream);
+        let mut lines = BufReader::new(read).lines();
+
+        let request = lines.next_line().await.unwrap().unwrap();
+        let request: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["method"], "send");
+        assert_eq!(request["params"]["recipient"][0], "+4512345678");
+
+        let response = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": request["id"],
+            "result": { "timestamp": 1700000000000u64, "results": [{ "type": "SUCCESS" }] },
+        });
+        let notification = r#"{"jsonrpc":"2.0","method":"receive","params":{"envelope":{"sourceNumber":"+4512345678"}}}"#;
+        let out = format!("{}\n{}\n", response, notification);
+        write.write_all(out.as_bytes()).await.unwrap();
+        write.flush().await.unwrap();
+
+        // Keep the socket open until the client is done with it
+        let _ = lines.next_line().await;
+    }
+
+    // Sends through the supervisor with nobody reading envelopes: the
+    // response must still come back on its own
+    async fn exercise(transport: Transport) {
+        let (out_tx, out_rx) = mpsc::channel(8);
+        let (in_tx, mut in_rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::new(out_tx));
+        tokio::spawn(supervise(transport, client.clone(), out_rx, in_tx));
+
+        let target = ReplyTarget::Direct("+4512345678".to_string());
+        let timestamp = client.send(&target, "hello", None, &[]).await.unwrap();
+        assert_eq!(timestamp, 1700000000000);
+
+        let envelope = in_rx.recv().await.unwrap();
+        assert_eq!(envelope.source_number.as_deref(), Some("+4512345678"));
+    }
+
+    #[tokio::test]
+    async fn talks_to_unix_socket_daemon() {
+        let path = std::env::temp_dir().join(format!("brainrot-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            fake_daemon(stream).await;
+        });
+
+        exercise(Transport::Unix(path.clone())).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn reconnects_with_backoff_after_the_daemon_dies() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let daemon = tokio::spawn(async move {
+            // Dies with a request in flight
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut lines = BufReader::new(stream).lines();
+            lines.next_line().await.unwrap().unwrap();
+            drop(lines);
+            let died = Instant::now();
+
+            let (stream, _) = listener.accept().await.unwrap();
+            let waited = died.elapsed();
+            tokio::spawn(fake_daemon(stream));
+            waited
+        });
+
+        let (out_tx, out_rx) = mpsc::channel(8);
+        let (in_tx, _in_rx) = mpsc::unbounded_channel();
+        let client = Arc::new(RpcClient::new(out_tx));
+        tokio::spawn(supervise(
+            Transport::Tcp(addr),
+            client.clone(),
+            out_rx,
+            in_tx,
+        ));
+
+        let target = ReplyTarget::Direct("+4512345678".to_string());
+        let lost = client.send(&target, "hello", None, &[]).await;
+        assert!(matches!(lost, Err(RpcError::Closed)));
+
+        // Held until the supervisor is back, then answered
+        let timestamp = client.send(&target, "hello", None, &[]).await.unwrap();
+        assert_eq!(timestamp, 1700000000000);
+        assert!(daemon.await.unwrap() >= INITIAL_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn talks_to_tcp_daemon() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            fake_daemon(stream).await;
+        });
+
+        exercise(Transport::Tcp(addr)).await;
+    }
+}
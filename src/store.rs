@@ -0,0 +1,711 @@
+use crate::bot::{Batch, Job};
+use crate::link::CanonicalVideo;
+use crate::pipeline::VideoSource;
+use crate::rpc::{Quote, ReplyTarget};
+use anyhow::{Context, Result};
+use rusqlite::{Connection, Row, params};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Lifecycle of a stored job:
+// queued -> running -> finished (reply ready) -> delivered
+// Batched jobs pass through sending while their combined reply goes out.
+const QUEUED: &str = "queued";
+const RUNNING: &str = "running";
+const FINISHED: &str = "finished";
+const SENDING: &str = "sending";
+const DELIVERED: &str = "delivered";
+
+// Schema changes, oldest first. Step n brings a database to user_version n;
+// only ever append, never edit a step that has shipped.
+const MIGRATIONS: &[&str] = &[
+    // Jobs and their replies
+    "CREATE TABLE jobs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        label TEXT NOT NULL,
+        source_url TEXT,
+        source_file TEXT,
+        reply_recipient TEXT,
+        reply_group TEXT,
+        quote_timestamp INTEGER,
+        quote_author TEXT,
+        quote_message TEXT,
+        status TEXT NOT NULL,
+        reply TEXT,
+        error TEXT,
+        created_at INTEGER NOT NULL,
+        updated_at INTEGER NOT NULL
+    );
+    CREATE INDEX jobs_status ON jobs (status);",
+    // Result cache
+    "ALTER TABLE jobs ADD COLUMN sender TEXT NOT NULL DEFAULT '';
+    ALTER TABLE jobs ADD COLUMN platform TEXT;
+    ALTER TABLE jobs ADD COLUMN video_id TEXT;
+    ALTER TABLE jobs ADD COLUMN cache_hit INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX jobs_video ON jobs (platform, video_id);",
+    // Several links answered in one reply
+    "ALTER TABLE jobs ADD COLUMN batch_key TEXT;
+    ALTER TABLE jobs ADD COLUMN batch_index INTEGER;
+    ALTER TABLE jobs ADD COLUMN batch_size INTEGER;
+    CREATE INDEX jobs_batch ON jobs (batch_key);",
+    // Reply and spoken languages
+    "ALTER TABLE jobs ADD COLUMN reply_language TEXT;
+    ALTER TABLE jobs ADD COLUMN language TEXT;",
+];
+
+// A reply that was produced but never made it out
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingReply {
+    pub id: i64,
+    pub reply_target: ReplyTarget,
+    pub quote: Option<Quote>,
+    pub reply: String,
+    // Batch key, if this reply is part of a combined one
+    pub batch: Option<String>,
+}
+
+// One video's share of a combined reply
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchPart {
+    pub id: i64,
+    // The link, or the label for attachments
+    pub source: String,
+    pub reply: String,
+    // The reply is an error message
+    pub failed: bool,
+}
+
+// Every job and its outcome, in SQLite, so a restart neither forgets queued
+// work nor swallows a reply that was ready but undelivered. Successful
+// summaries double as a result cache keyed by platform + video id.
+pub struct JobStore {
+    conn: Mutex<Connection>,
+}
+
+impl JobStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open job store {}", path.display()))?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> Result<Self> {
+        migrate(&mut conn).context("Failed to migrate job store")?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn insert(&self, job: &Job) -> Result<i64> {
+        self.insert_as(job, QUEUED, None, None)
+    }
+
+    // A job that failed before it could be queued, stored already finished
+    // with its error. Stands in for a part of a batch, which only goes out
+    // once every part has a reply.
+    pub fn insert_failed(&self, job: &Job, reply: &str, error: &str) -> Result<i64> {
+        self.insert_as(job, FINISHED, Some(reply), Some(error))
+    }
+
+    fn insert_as(
+        &self,
+        job: &Job,
+        status: &str,
+        reply: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<i64> {
+        let (source_url, source_file) = match &job.source {
+            VideoSource::Url(url) => (Some(url.clone()), None),
+            VideoSource::File(path) => (None, Some(path.to_string_lossy().into_owned())),
+        };
+        let (reply_recipient, reply_group) = match &job.reply_target {
+            ReplyTarget::Direct(recipient) => (Some(recipient.as_str()), None),
+            ReplyTarget::Group(group_id) => (None, Some(group_id.as_str())),
+        };
+        let now = now();
+
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO jobs (label, sender, platform, video_id, source_url, source_file,
+                reply_recipient, reply_group, quote_timestamp, quote_author, quote_message,
+                batch_key, batch_index, batch_size, reply_language, status, reply, error,
+                created_at, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                ?19, ?19)",
+            params![
+                job.label,
+                job.sender,
+                job.video.as_ref().map(|v| v.platform.as_str()),
+                job.video.as_ref().map(|v| v.id.as_str()),
+                source_url,
+                source_file,
+                reply_recipient,
+                reply_group,
+                job.quote.as_ref().map(|q| q.timestamp as i64),
+                job.quote.as_ref().map(|q| q.author.as_str()),
+                job.quote.as_ref().map(|q| q.message.as_str()),
+                job.batch.as_ref().map(|b| b.key.as_str()),
+                job.batch.as_ref().map(|b| b.index as i64),
+                job.batch.as_ref().map(|b| b.size as i64),
+                job.reply_language,
+                status,
+                reply,
+                error,
+                now,
+            ],
+        )
+        .context("Failed to store job")?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    // Forget a job that never made it into the queue
+    pub fn remove(&self, id: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM jobs WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn mark_running(&self, id: i64) -> Result<()> {
+        self.set_status(id, RUNNING)
+    }
+
+    // Record the outcome; `reply` is what the sender should get, `error` the raw details
+    pub fn mark_finished(&self, id: i64, reply: &str, error: Option<&str>) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = ?2, reply = ?3, error = ?4, updated_at = ?5 WHERE id = ?1",
+            params![id, FINISHED, reply, error, now()],
+        )?;
+        Ok(())
+    }
+
+    // Answered with an earlier job's summary instead of being analyzed
+    pub fn mark_cached(&self, id: i64, reply: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = ?2, reply = ?3, cache_hit = 1, updated_at = ?4 WHERE id = ?1",
+            params![id, FINISHED, reply, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn mark_delivered(&self, id: i64) -> Result<()> {
+        self.set_status(id, DELIVERED)
+    }
+
+    // The language the video turned out to be spoken in
+    pub fn set_language(&self, id: i64, language: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET language = ?2 WHERE id = ?1",
+            params![id, language],
+        )?;
+        Ok(())
+    }
+
+    // The newest summary of `video` analyzed less than `ttl` ago and written
+    // for `reply_language`. Cache hits don't count, so a popular video still
+    // expires on time.
+    pub fn cached(
+        &self,
+        video: &CanonicalVideo,
+        ttl: Duration,
+        reply_language: Option<&str>,
+    ) -> Result<Option<String>> {
+        let since = now() - ttl.as_secs() as i64;
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT reply FROM jobs
+             WHERE platform = ?1 AND video_id = ?2 AND status IN (?3, ?4)
+                AND error IS NULL AND cache_hit = 0 AND updated_at >= ?5
+                AND reply_language IS ?6
+             ORDER BY updated_at DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query(params![
+            video.platform,
+            video.id,
+            FINISHED,
+            DELIVERED,
+            since,
+            reply_language
+        ])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    // How many people other than `sender` sent us `video` in the last day
+    pub fn other_senders_today(&self, video: &CanonicalVideo, sender: &str) -> Result<usize> {
+        let since = now() - 24 * 60 * 60;
+        let conn = self.conn.lock().unwrap();
+        let count: i64 = conn.query_row(
+            "SELECT COUNT(DISTINCT sender) FROM jobs
+             WHERE platform = ?1 AND video_id = ?2 AND sender != ?3 AND created_at >= ?4",
+            params![video.platform, video.id, sender, since],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    // Jobs that were queued or mid-analysis when we last stopped
+    pub fn unfinished(&self) -> Result<Vec<(i64, Job)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, label, sender, platform, video_id, source_url, source_file,
+                reply_recipient, reply_group, quote_timestamp, quote_author, quote_message,
+                batch_key, batch_index, batch_size, reply_language
+             FROM jobs WHERE status IN (?1, ?2) ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![QUEUED, RUNNING], |row| {
+            let url: Option<String> = row.get(5)?;
+            let video = match (row.get(3)?, row.get(4)?, &url) {
+                (Some(platform), Some(id), Some(url)) => Some(CanonicalVideo {
+                    platform,
+                    id,
+                    url: url.clone(),
+                }),
+                _ => None,
+            };
+            let source = match url {
+                Some(url) => VideoSource::Url(url),
+                None => VideoSource::File(PathBuf::from(row.get::<_, String>(6)?)),
+            };
+            let job = Job {
+                source,
+                label: row.get(1)?,
+                sender: row.get(2)?,
+                video,
+                reply_target: reply_target(row, 7)?,
+                quote: quote(row, 9)?,
+                batch: batch(row, 12)?,
+                reply_language: row.get(15)?,
+            };
+            Ok((row.get(0)?, job))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Replies that were ready but never confirmed as sent
+    pub fn undelivered(&self) -> Result<Vec<PendingReply>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, reply, reply_recipient, reply_group,
+                quote_timestamp, quote_author, quote_message, batch_key
+             FROM jobs WHERE status = ?1 ORDER BY id",
+        )?;
+        let rows = stmt.query_map(params![FINISHED], |row| {
+            Ok(PendingReply {
+                id: row.get(0)?,
+                reply: row.get(1)?,
+                reply_target: reply_target(row, 2)?,
+                quote: quote(row, 4)?,
+                batch: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Once every job of the batch has finished, hand out their replies in
+    // message order and mark them as sending, so exactly one caller sends
+    // the combined reply. None while some are still in progress.
+    pub fn complete_batch(&self, key: &str) -> Result<Option<Vec<BatchPart>>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, COALESCE(source_url, label), reply, batch_size, error IS NOT NULL FROM jobs
+             WHERE batch_key = ?1 AND status = ?2 ORDER BY batch_index",
+        )?;
+        let mut size = 0;
+        let parts = stmt
+            .query_map(params![key, FINISHED], |row| {
+                size = row.get::<_, i64>(3)? as usize;
+                Ok(BatchPart {
+                    id: row.get(0)?,
+                    source: row.get(1)?,
+                    reply: row.get(2)?,
+                    failed: row.get(4)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        if parts.is_empty() || parts.len() < size {
+            return Ok(None);
+        }
+        conn.execute(
+            "UPDATE jobs SET status = ?3, updated_at = ?4 WHERE batch_key = ?1 AND status = ?2",
+            params![key, FINISHED, SENDING, now()],
+        )?;
+        Ok(Some(parts))
+    }
+
+    // The combined reply couldn't be delivered: hand the batch back, so it
+    // goes out again like any other undelivered reply
+    pub fn release_batch(&self, key: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = ?3, updated_at = ?4 WHERE batch_key = ?1 AND status = ?2",
+            params![key, SENDING, FINISHED, now()],
+        )?;
+        Ok(())
+    }
+
+    // After a restart, combined replies that were mid-send go out again
+    pub fn unclaim_batches(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = ?2 WHERE status = ?1",
+            params![SENDING, FINISHED],
+        )?;
+        Ok(())
+    }
+
+    // Make inserts of rows matching `condition` (SQL over NEW) fail, the
+    // way a full disk or a locked database would
+    #[cfg(test)]
+    pub fn fail_inserts_where(&self, condition: &str) {
+        let conn = self.conn.lock().unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TRIGGER fail_inserts BEFORE INSERT ON jobs WHEN {} \
+             BEGIN SELECT RAISE(ABORT, 'database or disk is full'); END;",
+            condition
+        ))
+        .unwrap();
+    }
+
+    #[cfg(test)]
+    pub fn status(&self, id: i64) -> Option<String> {
+        use rusqlite::OptionalExtension;
+        let conn = self.conn.lock().unwrap();
+        conn.query_row(
+            "SELECT status FROM jobs WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    fn set_status(&self, id: i64, status: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE jobs SET status = ?2, updated_at = ?3 WHERE id = ?1",
+            params![id, status, now()],
+        )?;
+        Ok(())
+    }
+}
+
+// Reads the reply_recipient / reply_group column pair starting at `idx`
+fn reply_target(row: &Row, idx: usize) -> rusqlite::Result<ReplyTarget> {
+    match row.get::<_, Option<String>>(idx)? {
+        Some(recipient) => Ok(ReplyTarget::Direct(recipient)),
+        None => Ok(ReplyTarget::Group(row.get(idx + 1)?)),
+    }
+}
+
+// Reads the three quote_* columns starting at `idx`
+fn quote(row: &Row, idx: usize) -> rusqlite::Result<Option<Quote>> {
+    let Some(timestamp) = row.get::<_, Option<i64>>(idx)? else {
+        return Ok(None);
+    };
+    Ok(Some(Quote {
+        timestamp: timestamp as u64,
+        author: row.get(idx + 1)?,
+        message: row.get(idx + 2)?,
+    }))
+}
+
+// Reads the three batch_* columns starting at `idx`
+fn batch(row: &Row, idx: usize) -> rusqlite::Result<Option<Batch>> {
+    let Some(key) = row.get::<_, Option<String>>(idx)? else {
+        return Ok(None);
+    };
+    Ok(Some(Batch {
+        key,
+        index: row.get::<_, i64>(idx + 1)? as usize,
+        size: row.get::<_, i64>(idx + 2)? as usize,
+    }))
+}
+
+// Bring the schema up to date, one transaction per step
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    let mut version = version as usize;
+    if version == 0 {
+        version = unversioned(conn)?;
+    }
+    for (n, step) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(step)?;
+        tx.pragma_update(None, "user_version", (n + 1) as i64)?;
+        tx.commit()
+            .with_context(|| format!("Failed to apply schema version {}", n + 1))?;
+    }
+    Ok(())
+}
+
+// Builds before migrations created the whole table in one go and never set
+// user_version; tell which schema they left behind by its newest column
+fn unversioned(conn: &Connection) -> Result<usize> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('jobs')")?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let has = |column: &str| columns.iter().any(|c| c == column);
+    Ok(if columns.is_empty() {
+        0
+    } else if has("language") {
+        4
+    } else if has("batch_key") {
+        3
+    } else if has("cache_hit") {
+        2
+    } else {
+        1
+    })
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user_version(store: &JobStore) -> usize {
+        let conn = store.conn.lock().unwrap();
+        let version: i64 = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        version as usize
+    }
+
+    #[test]
+    fn upgrades_databases_from_older_builds() {
+        // As the first build that stored jobs left it
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute(
+            "INSERT INTO jobs (label, source_url, reply_recipient, status, created_at, updated_at)
+             VALUES ('TikTok', 'https://www.tiktok.com/@a/video/1', '+4511111111', 'queued', 1, 1)",
+            [],
+        )
+        .unwrap();
+        conn.pragma_update(None, "user_version", 1).unwrap();
+
+        let store = JobStore::init(conn).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        let unfinished = store.unfinished().unwrap();
+        assert_eq!(unfinished.len(), 1);
+        assert_eq!(unfinished[0].1.batch, None);
+        store.insert(&unfinished[0].1).unwrap();
+    }
+
+    #[test]
+    fn recognizes_schemas_from_before_versioning() {
+        // Created in one go with the batch columns, user_version never set
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(&MIGRATIONS[..3].join("\n")).unwrap();
+
+        let store = JobStore::init(conn).unwrap();
+        assert_eq!(user_version(&store), MIGRATIONS.len());
+        let fresh = JobStore::open_in_memory().unwrap();
+        assert_eq!(user_version(&fresh), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn tracks_jobs_through_their_lifecycle() {
+        let store = JobStore::open_in_memory().unwrap();
+        let job = Job {
+            source: VideoSource::Url("https://www.tiktok.com/@user/video/1".to_string()),
+            label: "TikTok".to_string(),
+            sender: "+4511111111".to_string(),
+            video: Some(CanonicalVideo {
+                platform: "tiktok".to_string(),
+                id: "1".to_string(),
+                url: "https://www.tiktok.com/@user/video/1".to_string(),
+            }),
+            reply_target: ReplyTarget::Group("Z3JvdXA=".to_string()),
+            quote: Some(Quote {
+                timestamp: 42,
+                author: "+4511111111".to_string(),
+                message: "look".to_string(),
+            }),
+            batch: None,
+            reply_language: None,
+        };
+
+        let id = store.insert(&job).unwrap();
+        assert_eq!(store.unfinished().unwrap(), vec![(id, job.clone())]);
+
+        store.mark_running(id).unwrap();
+        assert_eq!(store.unfinished().unwrap().len(), 1);
+
+        store.mark_finished(id, "Summary", None).unwrap();
+        assert!(store.unfinished().unwrap().is_empty());
+        let pending = store.undelivered().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].reply, "Summary");
+        assert_eq!(pending[0].reply_target, job.reply_target);
+        assert_eq!(pending[0].quote, job.quote);
+
+        store.mark_delivered(id).unwrap();
+        assert!(store.undelivered().unwrap().is_empty());
+        assert_eq!(store.status(id).as_deref(), Some(DELIVERED));
+
+        let ttl = Duration::from_secs(3600);
+        let video = job.video.clone().unwrap();
+        assert_eq!(
+            store.cached(&video, ttl, None).unwrap().as_deref(),
+            Some("Summary")
+        );
+        let other = CanonicalVideo {
+            id: "2".to_string(),
+            ..video.clone()
+        };
+        assert_eq!(store.cached(&other, ttl, None).unwrap(), None);
+
+        // A second sender is answered from the cache and counted
+        let repeat = Job {
+            sender: "+4522222222".to_string(),
+            ..job.clone()
+        };
+        let repeat_id = store.insert(&repeat).unwrap();
+        store.mark_cached(repeat_id, "Summary").unwrap();
+        assert_eq!(
+            store.other_senders_today(&video, &repeat.sender).unwrap(),
+            1
+        );
+        assert_eq!(store.other_senders_today(&video, "+4533333333").unwrap(), 2);
+    }
+
+    #[test]
+    fn failed_analyses_are_not_cached() {
+        let store = JobStore::open_in_memory().unwrap();
+        let video = CanonicalVideo {
+            platform: "instagram".to_string(),
+            id: "C1abc".to_string(),
+            url: "https://www.instagram.com/reel/C1abc/".to_string(),
+        };
+        let job = Job {
+            source: VideoSource::Url("https://www.instagram.com/reel/C1abc/".to_string()),
+            label: "Instagram".to_string(),
+            sender: "+4511111111".to_string(),
+            video: Some(video.clone()),
+            reply_target: ReplyTarget::Direct("+4511111111".to_string()),
+            quote: None,
+            batch: None,
+            reply_language: None,
+        };
+        let id = store.insert(&job).unwrap();
+        store
+            .mark_finished(id, "🔒 private", Some("video unavailable"))
+            .unwrap();
+        assert_eq!(
+            store
+                .cached(&video, Duration::from_secs(3600), None)
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn caches_summaries_per_reply_language() {
+        let store = JobStore::open_in_memory().unwrap();
+        let video = CanonicalVideo {
+            platform: "tiktok".to_string(),
+            id: "7".to_string(),
+            url: "https://www.tiktok.com/@a/video/7".to_string(),
+        };
+        let job = Job {
+            source: VideoSource::Url(video.url.clone()),
+            label: "TikTok".to_string(),
+            sender: "+4511111111".to_string(),
+            video: Some(video.clone()),
+            reply_target: ReplyTarget::Direct("+4511111111".to_string()),
+            quote: None,
+            batch: None,
+            reply_language: Some("da".to_string()),
+        };
+        let id = store.insert(&job).unwrap();
+        assert_eq!(store.unfinished().unwrap(), vec![(id, job.clone())]);
+        store.mark_finished(id, "Opsummering", None).unwrap();
+        store.set_language(id, "es").unwrap();
+
+        let ttl = Duration::from_secs(3600);
+        assert_eq!(
+            store.cached(&video, ttl, Some("da")).unwrap().as_deref(),
+            Some("Opsummering")
+        );
+        assert_eq!(store.cached(&video, ttl, None).unwrap(), None);
+        assert_eq!(store.cached(&video, ttl, Some("en")).unwrap(), None);
+        let language: Option<String> = store
+            .conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT language FROM jobs WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(language.as_deref(), Some("es"));
+    }
+
+    #[test]
+    fn hands_out_a_finished_batch_once() {
+        let store = JobStore::open_in_memory().unwrap();
+        let job = |index: usize| Job {
+            source: VideoSource::Url(format!("https://www.tiktok.com/@a/video/{}", index)),
+            label: "TikTok".to_string(),
+            sender: "+4511111111".to_string(),
+            video: None,
+            reply_target: ReplyTarget::Direct("+4511111111".to_string()),
+            quote: None,
+            batch: Some(Batch {
+                key: "+4511111111:42".to_string(),
+                index,
+                size: 2,
+            }),
+            reply_language: None,
+        };
+        let second = store.insert(&job(1)).unwrap();
+        let first = store.insert(&job(0)).unwrap();
+        assert_eq!(store.unfinished().unwrap()[0].1, job(1));
+
+        store.mark_finished(second, "Two", None).unwrap();
+        assert_eq!(store.complete_batch("+4511111111:42").unwrap(), None);
+
+        store.mark_finished(first, "One", None).unwrap();
+        let parts = store.complete_batch("+4511111111:42").unwrap().unwrap();
+        let replies: Vec<&str> = parts.iter().map(|p| p.reply.as_str()).collect();
+        assert_eq!(replies, ["One", "Two"]);
+        assert_eq!(parts[0].source, "https://www.tiktok.com/@a/video/0");
+        assert!(!parts[0].failed);
+        assert_eq!(store.complete_batch("+4511111111:42").unwrap(), None);
+
+        // Delivery failed: up for grabs again
+        store.release_batch("+4511111111:42").unwrap();
+        assert_eq!(store.undelivered().unwrap().len(), 2);
+        assert!(store.complete_batch("+4511111111:42").unwrap().is_some());
+
+        // Interrupted while sending: goes out again after a restart
+        store.unclaim_batches().unwrap();
+        assert!(store.complete_batch("+4511111111:42").unwrap().is_some());
+    }
+}
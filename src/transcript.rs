@@ -0,0 +1,341 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+
+// <c>, <c.colorE5E5E5>, <00:00:01.120>, <i>, <font color="..."> and friends
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+// SRT's {\an8} positioning
+static SSA_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\\[^}]*\}").unwrap());
+
+// One caption: what is said between `start` and `end` (seconds)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+// transcript.json
+#[derive(Debug, Serialize)]
+struct Transcript<'a> {
+    // The subtitle file it was made from, relative to the work directory
+    source: &'a str,
+    // What is spoken, as a language code, when known
+    language: Option<&'a str>,
+    cues: &'a [Cue],
+}
+
+// Cues of a WebVTT or SRT file, as written. Both are blocks separated by
+// blank lines with a "start --> end" line followed by the text; anything
+// without one (the WEBVTT header, NOTE, STYLE and REGION blocks, broken
+// cues) is skipped.
+pub fn parse(content: &str) -> Vec<Cue> {
+    let content = content.trim_start_matches('\u{feff}').replace("\r\n", "\n");
+    let mut cues = Vec::new();
+    for block in content.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some(timing) = lines.next() else {
+            continue;
+        };
+        let Some((start, end)) = parse_timing(timing) else {
+            continue;
+        };
+        let text = lines.collect::<Vec<_>>().join("\n");
+        cues.push(Cue { start, end, text });
+    }
+    cues
+}
+
+// "00:00:01.120 --> 00:00:03.450 align:start position:0%"
+fn parse_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_timestamp(start.trim())?, parse_timestamp(end)?))
+}
+
+// hh:mm:ss.mmm, mm:ss.mmm, or SRT's hh:mm:ss,mmm
+fn parse_timestamp(value: &str) -> Option<f64> {
+    let value = value.replace(',', ".");
+    let mut seconds = 0.0;
+    for part in value.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+// Markup-free, single-spaced text, one entry per caption line
+fn clean_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            let line = TAG_REGEX.replace_all(line, "");
+            let line = SSA_REGEX.replace_all(&line, "");
+            decode_entities(&line)
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+fn decode_entities(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Turn captions into something worth reading. Auto-captions scroll: every
+// cue repeats the line before it, then a 10ms cue repeats the new line once
+// more, and some captions grow word by word. Lines already shown by the
+// previous cue are dropped, repeats extend the cue they repeat, and a
+// caption that grows replaces its shorter self.
+pub fn normalize(cues: Vec<Cue>) -> Vec<Cue> {
+    let mut merged: Vec<Cue> = Vec::new();
+    let mut shown: Vec<String> = Vec::new();
+    for cue in cues {
+        let lines = clean_lines(&cue.text);
+        let text = lines
+            .iter()
+            .filter(|line| !shown.contains(line))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" ");
+        shown = lines;
+
+        if let Some(last) = merged.last_mut() {
+            if text.is_empty() || text == last.text {
+                last.end = last.end.max(cue.end);
+                continue;
+            }
+            if text.starts_with(&format!("{} ", last.text)) {
+                last.text = text;
+                last.end = last.end.max(cue.end);
+                continue;
+            }
+        }
+        if !text.is_empty() {
+            merged.push(Cue {
+                start: cue.start,
+                end: cue.end,
+                text,
+            });
+        }
+    }
+    merged
+}
+
+// Parse and normalize `subtitles`, then write transcript.txt and
+// transcript.json into `work_dir`. Returns the number of cues.
+pub fn write(work_dir: &Path, subtitles: &Path, language: Option<&str>) -> Result<usize> {
+    let content = fs::read_to_string(subtitles)
+        .with_context(|| format!("Failed to read {}", subtitles.display()))?;
+    let cues = normalize(parse(&content));
+
+    let text: String = cues
+        .iter()
+        .map(|cue| format!("[{}] {}\n", clock(cue.start), cue.text))
+        .collect();
+    fs::write(work_dir.join("transcript.txt"), text).context("Failed to write transcript.txt")?;
+
+    let source = subtitles.strip_prefix(work_dir).unwrap_or(subtitles);
+    let json = serde_json::to_string_pretty(&Transcript {
+        source: &source.to_string_lossy(),
+        language,
+        cues: &cues,
+    })?;
+    fs::write(work_dir.join("transcript.json"), json).context("Failed to write transcript.json")?;
+    Ok(cues.len())
+}
+
+// 65.4 -> "1:05"
+fn clock(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    if seconds >= 3600 {
+        format!(
+            "{}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        )
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The start of a YouTube Shorts auto-caption file from yt-dlp
+    const ROLLING_VTT: &str = "WEBVTT
+Kind: captions
+Language: en
+
+00:00:00.160 --> 00:00:02.070 align:start position:0%
+ 
+so<00:00:00.320><c> I</c><00:00:00.480><c> tried</c><00:00:00.719><c> the</c><00:00:00.880><c> viral</c>
+
+00:00:02.070 --> 00:00:02.080 align:start position:0%
+so I tried the viral
+
+
+00:00:02.080 --> 00:00:04.630 align:start position:0%
+so I tried the viral
+pasta<00:00:02.399><c> recipe</c><00:00:02.800><c> and</c><00:00:03.040><c> honestly</c>
+
+00:00:04.630 --> 00:00:04.640 align:start position:0%
+pasta recipe and honestly
+
+
+00:00:04.640 --> 00:00:06.950 align:start position:0%
+pasta recipe and honestly
+it's<00:00:04.960><c> mid</c>
+";
+
+    // whisper --output_format vtt
+    const WHISPER_VTT: &str = "WEBVTT
+
+00:00.000 --> 00:03.520
+ Okay, so this is day 47 of asking my landlord to fix the sink.
+
+00:03.520 --> 00:07.040
+ He said &quot;next week&quot; &amp; that was in March.
+";
+
+    const SRT: &str = "1\r
+00:00:01,000 --> 00:00:02,500\r
+<i>[music]</i>\r
+\r
+2\r
+00:00:02,500 --> 00:00:05,000\r
+{\\an8}Wait for it...\r
+\r
+3\r
+00:00:05,000 --> 00:00:06,200\r
+<font color=\"#ffff00\">WAIT FOR IT</font>\r
+\r
+";
+
+    const STYLED_VTT: &str = "\u{feff}WEBVTT
+
+STYLE
+::cue { color: yellow }
+
+NOTE generated by a TikTok caption export
+
+intro
+00:00:00.000 --> 00:00:01.500 line:85%
+<v Narrator><b>POV:</b> you&#39;re the
+group chat&nbsp;&nbsp;admin
+";
+
+    fn texts(cues: &[Cue]) -> Vec<&str> {
+        cues.iter().map(|c| c.text.as_str()).collect()
+    }
+
+    #[test]
+    fn merges_rolling_auto_captions() {
+        let cues = normalize(parse(ROLLING_VTT));
+        assert_eq!(
+            texts(&cues),
+            [
+                "so I tried the viral",
+                "pasta recipe and honestly",
+                "it's mid"
+            ]
+        );
+        // The 10ms repeats extend the cue they repeat
+        assert_eq!((cues[0].start, cues[0].end), (0.16, 2.08));
+        assert_eq!((cues[1].start, cues[1].end), (2.08, 4.64));
+    }
+
+    #[test]
+    fn reads_whisper_output() {
+        let cues = normalize(parse(WHISPER_VTT));
+        assert_eq!(
+            texts(&cues),
+            [
+                "Okay, so this is day 47 of asking my landlord to fix the sink.",
+                "He said \"next week\" & that was in March."
+            ]
+        );
+        assert_eq!(cues[1].start, 3.52);
+        assert_eq!(cues[1].end, 7.04);
+    }
+
+    #[test]
+    fn reads_srt() {
+        let cues = normalize(parse(SRT));
+        assert_eq!(texts(&cues), ["[music]", "Wait for it...", "WAIT FOR IT"]);
+        assert_eq!(cues[1].start, 2.5);
+    }
+
+    #[test]
+    fn skips_header_blocks_and_strips_styling() {
+        let cues = normalize(parse(STYLED_VTT));
+        assert_eq!(texts(&cues), ["POV: you're the group chat admin"]);
+        assert_eq!(cues[0].end, 1.5);
+    }
+
+    #[test]
+    fn grows_word_by_word_captions() {
+        let cues = vec![
+            Cue {
+                start: 0.0,
+                end: 0.5,
+                text: "no".to_string(),
+            },
+            Cue {
+                start: 0.5,
+                end: 1.0,
+                text: "no way".to_string(),
+            },
+            Cue {
+                start: 1.0,
+                end: 2.0,
+                text: "no way bro".to_string(),
+            },
+        ];
+        let cues = normalize(cues);
+        assert_eq!(texts(&cues), ["no way bro"]);
+        assert_eq!((cues[0].start, cues[0].end), (0.0, 2.0));
+    }
+
+    #[test]
+    fn writes_text_and_json_transcripts() {
+        let dir = std::env::temp_dir().join(format!("brainrot-transcript-{}", std::process::id()));
+        fs::create_dir_all(dir.join("subs")).unwrap();
+        let subs = dir.join("subs").join("video.en.vtt");
+        fs::write(&subs, ROLLING_VTT).unwrap();
+
+        assert_eq!(write(&dir, &subs, Some("en")).unwrap(), 3);
+        let text = fs::read_to_string(dir.join("transcript.txt")).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("transcript.json")).unwrap())
+                .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            text,
+            "[0:00] so I tried the viral\n[0:02] pasta recipe and honestly\n[0:04] it's mid\n"
+        );
+        assert_eq!(json["source"], "subs/video.en.vtt");
+        assert_eq!(json["language"], "en");
+        assert_eq!(json["cues"][2]["text"], "it's mid");
+        assert_eq!(json["cues"][2]["start"], 4.64);
+    }
+
+    #[test]
+    fn formats_clock_times() {
+        assert_eq!(clock(4.9), "0:04");
+        assert_eq!(clock(65.4), "1:05");
+        assert_eq!(clock(3725.0), "1:02:05");
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static NEXT_JOB: AtomicU64 = AtomicU64::new(1);
+
+// What happens to a job's directory once the job is over
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Retention {
+    // Always delete it
+    #[default]
+    Delete,
+    // Keep it around for debugging when the analysis failed
+    KeepFailed,
+    // Never delete it
+    Keep,
+}
+
+impl Retention {
+    // Accepts "delete", "keep-failed" or "keep"
+    pub fn parse(value: &str) -> Result<Self> {
+        match value.trim() {
+            "" | "delete" => Ok(Retention::Delete),
+            "keep-failed" => Ok(Retention::KeepFailed),
+            "keep" => Ok(Retention::Keep),
+            other => bail!(
+                "expected 'delete', 'keep-failed' or 'keep', got '{}'",
+                other
+            ),
+        }
+    }
+}
+
+// A private working directory for one analysis, so concurrent jobs never see
+// each other's video, frames or subtitles. Cleaned up on drop according to
+// the retention policy; a job that never called `succeeded` counts as failed.
+pub struct Workspace {
+    path: PathBuf,
+    retention: Retention,
+    succeeded: bool,
+}
+
+impl Workspace {
+    pub fn create(root: &Path, retention: Retention) -> Result<Self> {
+        fs::create_dir_all(root)
+            .with_context(|| format!("Failed to create work root {}", root.display()))?;
+
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let name = format!(
+            "job-{}-{}-{}",
+            millis,
+            std::process::id(),
+            NEXT_JOB.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = root.join(name);
+        // create_dir (not _all) so we never silently share a directory
+        fs::create_dir(&path)
+            .with_context(|| format!("Failed to create workspace {}", path.display()))?;
+
+        Ok(Self {
+            path,
+            retention,
+            succeeded: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn succeeded(&mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let keep = match self.retention {
+            Retention::Delete => false,
+            Retention::KeepFailed => !self.succeeded,
+            Retention::Keep => true,
+        };
+        if keep {
+            println!("[DEBUG] Keeping workspace {}", self.path.display());
+        } else if let Err(e) = fs::remove_dir_all(&self.path) {
+            eprintln!(
+                "Warning: Failed to clean up workspace {}: {}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn root(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("brainrot-ws-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn concurrent_jobs_get_their_own_directory() {
+        let root = Arc::new(root("concurrent"));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let root = root.clone();
+                std::thread::spawn(move || Workspace::create(&root, Retention::Keep).unwrap())
+            })
+            .collect();
+        let mut paths: Vec<PathBuf> = threads
+            .into_iter()
+            .map(|t| t.join().unwrap().path().to_path_buf())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        let created = fs::read_dir(root.as_path()).unwrap().count();
+        fs::remove_dir_all(root.as_path()).unwrap();
+
+        assert_eq!(paths.len(), 8);
+        assert_eq!(created, 8);
+    }
+
+    // Whether the workspace is still there after a job with this outcome
+    fn kept(retention: Retention, succeeded: bool) -> bool {
+        let root = root(&format!("{:?}-{}", retention, succeeded));
+        let mut workspace = Workspace::create(&root, retention).unwrap();
+        fs::write(workspace.path().join("video.mp4"), "video").unwrap();
+        if succeeded {
+            workspace.succeeded();
+        }
+        let path = workspace.path().to_path_buf();
+        drop(workspace);
+        let kept = path.exists();
+        fs::remove_dir_all(&root).unwrap();
+        kept
+    }
+
+    #[test]
+    fn honours_the_retention_policy() {
+        assert!(!kept(Retention::Delete, true));
+        assert!(!kept(Retention::Delete, false));
+        assert!(!kept(Retention::KeepFailed, true));
+        assert!(kept(Retention::KeepFailed, false));
+        assert!(kept(Retention::Keep, true));
+        assert!(kept(Retention::Keep, false));
+    }
+}